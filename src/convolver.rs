@@ -0,0 +1,563 @@
+use crate::gpu_fft::GpuInverseFft;
+use crate::options::{ConvolutionOptions, MAX_IMPULSE_RESPONSE_SECONDS};
+use crate::wgpu_wrapper::glsl_to_wgsl;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::sync::Arc;
+use wgpu::{util::DeviceExt, *};
+
+/// Frames of each partition of the impulse response
+const CONVOLUTION_BLOCK: usize = 1024;
+
+/// Sums the products of the partitions of the impulse response except the first one and the
+/// FFTs of the past blocks. Each invocation computes a bin of the channel
+/// `gl_GlobalInvocationID.y`. The slot `head` of `history` has the newest block.
+const PARTITIONS_SHADER: &str = "#version 450
+layout(local_size_x = 64) in;
+
+layout(set = 0, binding = 0) buffer Partitions {
+	vec2[] partitions;
+};
+
+layout(set = 0, binding = 1) buffer History {
+	vec2[] history;
+};
+
+layout(set = 0, binding = 2) buffer Tail {
+	vec2[] tail;
+};
+
+layout(set = 0, binding = 3) uniform Info {
+	uint size;
+	uint slots;
+	uint head;
+};
+
+void main() {
+	uint i = gl_GlobalInvocationID.x;
+	uint channel = gl_GlobalInvocationID.y;
+	vec2 sum = vec2(0.0);
+	for (uint k = 0u; k < slots; k += 1u) {
+		vec2 x = history[(channel * slots + (head + slots - k) % slots) * size + i];
+		vec2 h = partitions[(channel * slots + k) * size + i];
+		sum += vec2(x.x * h.x - x.y * h.y, x.x * h.y + x.y * h.x);
+	}
+	tail[channel * size + i] = sum;
+}
+";
+
+/// Convolution of each channel of a stream, by the uniformly partitioned overlap-save method.
+///
+/// There is no latency: the samples of an incomplete block are convolved with the first
+/// partition again when the block is completed, on the CPU. The other partitions are
+/// multiplied with the past blocks on GPU by `GpuPartitions` once for each complete block.
+/// The length of the response is limited by `MAX_IMPULSE_RESPONSE_SECONDS`.
+pub(crate) struct Convolver {
+	channels: usize,
+	wet: f32,
+	dry: f32,
+	fft: Arc<dyn Fft<f32>>,
+	ifft: Arc<dyn Fft<f32>>,
+	/// FFTs of the first partition of each channel of the impulse response
+	partitions: Vec<Vec<Complex<f32>>>,
+	/// Frames of the impulse response
+	len: usize,
+	/// The other partitions, if the response is longer than a partition
+	gpu: Option<GpuPartitions>,
+	/// Output samples of the current block of each channel convolved by the other partitions
+	tail: Vec<Vec<f32>>,
+	/// The previous block and the current block of each channel
+	input: Vec<Vec<f32>>,
+	/// Frames of the current block
+	filled: usize,
+}
+
+impl Convolver {
+	/// Creates from the samples of the channels of an impulse response.
+	pub fn new(
+		impulse_response: &[Vec<f32>],
+		channels: usize,
+		wet: f32,
+		dry: f32,
+		device: &Arc<Device>,
+		queue: &Arc<Queue>,
+	) -> Self {
+		let mut planner = FftPlanner::new();
+		let fft = planner.plan_fft_forward(CONVOLUTION_BLOCK * 2);
+		let ifft = planner.plan_fft_inverse(CONVOLUTION_BLOCK * 2);
+		let len = impulse_response.iter().map(Vec::len).max().unwrap_or(0);
+		let count = usize::max(len.div_ceil(CONVOLUTION_BLOCK), 1);
+		let partitions: Vec<Vec<Vec<Complex<f32>>>> = impulse_response
+			.iter()
+			.map(|response| {
+				(0..count)
+					.map(|k| {
+						let mut buffer = vec![Complex::default(); CONVOLUTION_BLOCK * 2];
+						response
+							.iter()
+							.skip(k * CONVOLUTION_BLOCK)
+							.take(CONVOLUTION_BLOCK)
+							.zip(&mut buffer)
+							.for_each(|(x, y)| y.re = *x);
+						fft.process(&mut buffer);
+						buffer
+					})
+					.collect()
+			})
+			.collect();
+		let gpu = if count > 1 {
+			let responses: Vec<&[Vec<Complex<f32>>]> = (0..channels)
+				.map(|channel| &partitions[usize::min(channel, partitions.len() - 1)][1..])
+				.collect();
+			Some(GpuPartitions::new(device, queue, &responses))
+		} else {
+			None
+		};
+		Self {
+			channels,
+			wet,
+			dry,
+			fft,
+			ifft,
+			partitions: partitions
+				.into_iter()
+				.map(|mut response| response.swap_remove(0))
+				.collect(),
+			len,
+			gpu,
+			tail: vec![vec![0.0; CONVOLUTION_BLOCK]; channels],
+			input: vec![vec![0.0; CONVOLUTION_BLOCK * 2]; channels],
+			filled: 0,
+		}
+	}
+
+	/// Loads the impulse response of `options`, resampled to `sample_rate` by linear
+	/// interpolation. The response is truncated to `MAX_IMPULSE_RESPONSE_SECONDS`.
+	pub fn load(
+		options: &ConvolutionOptions,
+		sample_rate: u32,
+		channels: usize,
+		device: &Arc<Device>,
+		queue: &Arc<Queue>,
+	) -> Result<Self, String> {
+		let path = &options.impulse_response;
+		let (samples, spec) = crate::sound_source::open_samples(path)
+			.map_err(|e| format!("{}: {}", path.display(), e))?;
+		let response_channels = spec.channels as usize;
+		let max_len = spec.sample_rate as usize * response_channels * MAX_IMPULSE_RESPONSE_SECONDS;
+		let samples: Vec<f32> = samples.take(max_len).collect();
+		let frames = samples.len() / response_channels;
+		let ratio = spec.sample_rate as f64 / sample_rate as f64;
+		let len = (frames as f64 / ratio) as usize;
+		let impulse_response: Vec<Vec<f32>> = (0..channels)
+			.map(|channel| {
+				let channel = usize::min(channel, response_channels - 1);
+				let sample = |i: usize| {
+					if i < frames {
+						samples[i * response_channels + channel]
+					} else {
+						0.0
+					}
+				};
+				(0..len)
+					.map(|i| {
+						let t = i as f64 * ratio;
+						let (idx, p) = (t as usize, t.fract() as f32);
+						// the gain is kept when the response is upsampled or downsampled.
+						(sample(idx) * (1.0 - p) + sample(idx + 1) * p) * ratio as f32
+					})
+					.collect()
+			})
+			.collect();
+		Ok(Self::new(
+			&impulse_response,
+			channels,
+			options.wet,
+			options.dry,
+			device,
+			queue,
+		))
+	}
+
+	/// Frames of the impulse response
+	pub fn len(&self) -> usize {
+		self.len
+	}
+
+	/// Convolves interleaved samples, mixed by the wet and dry gains.
+	pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+		let mut res = Vec::with_capacity(samples.len());
+		let mut frames = samples.chunks_exact(self.channels).peekable();
+		while frames.peek().is_some() {
+			let begin = self.filled;
+			for frame in frames.by_ref().take(CONVOLUTION_BLOCK - begin) {
+				let idx = CONVOLUTION_BLOCK + self.filled;
+				self.input
+					.iter_mut()
+					.zip(frame)
+					.for_each(|(input, x)| input[idx] = *x);
+				self.filled += 1;
+			}
+			self.convolve_block(begin, &mut res);
+		}
+		res
+	}
+
+	/// Appends the output of the frames `begin..filled` of the current block to `res`,
+	/// and moves to the next block if the current block is complete.
+	fn convolve_block(&mut self, begin: usize, res: &mut Vec<f32>) {
+		let Self {
+			channels,
+			wet,
+			dry,
+			fft,
+			ifft,
+			partitions,
+			gpu,
+			tail,
+			input,
+			filled,
+			..
+		} = self;
+		let complete = *filled == CONVOLUTION_BLOCK;
+		let mut spectra = Vec::new();
+		let outputs: Vec<Vec<f32>> = (0..*channels)
+			.map(|channel| {
+				let partition = &partitions[usize::min(channel, partitions.len() - 1)];
+				let input = &mut input[channel];
+				// the rest of the current block is zero until it is filled.
+				input[CONVOLUTION_BLOCK + *filled..]
+					.iter_mut()
+					.for_each(|x| *x = 0.0);
+				let mut spectrum: Vec<Complex<f32>> =
+					input.iter().map(|x| Complex::new(*x, 0.0)).collect();
+				fft.process(&mut spectrum);
+				let mut output: Vec<Complex<f32>> =
+					spectrum.iter().zip(partition).map(|(x, h)| x * h).collect();
+				ifft.process(&mut output);
+				let scale = 1.0 / (CONVOLUTION_BLOCK * 2) as f32;
+				let tail = &tail[channel];
+				let output = (begin..*filled)
+					.map(|i| {
+						let x = input[CONVOLUTION_BLOCK + i];
+						let y = output[CONVOLUTION_BLOCK + i].re * scale + tail[i];
+						x * *dry + y * *wet
+					})
+					.collect();
+				if complete {
+					spectra.push(spectrum);
+					input.copy_within(CONVOLUTION_BLOCK.., 0);
+				}
+				output
+			})
+			.collect();
+		(0..*filled - begin).for_each(|i| res.extend(outputs.iter().map(|output| output[i])));
+		if complete {
+			if let Some(gpu) = gpu.as_mut() {
+				*tail = gpu.accumulate(&spectra);
+			}
+			*filled = 0;
+		}
+	}
+}
+
+/// The partitions of the impulse response except the first one, and the FFTs of the recent
+/// complete blocks, on GPU.
+struct GpuPartitions {
+	device: Arc<Device>,
+	queue: Arc<Queue>,
+	channels: usize,
+	/// The number of partitions, and of the blocks in `history`
+	slots: usize,
+	/// The slot of `history` which has the newest block
+	head: usize,
+	pipeline: ComputePipeline,
+	bind_group: BindGroup,
+	fft: GpuInverseFft,
+	_partitions: Buffer,
+	/// FFTs of the recent complete blocks of each channel
+	history: Buffer,
+	/// `Info` of `PARTITIONS_SHADER`
+	info: Buffer,
+	/// The sums of the products, and the work buffer of the inverse FFT
+	buffers: [Buffer; 2],
+	staging: Buffer,
+}
+
+impl GpuPartitions {
+	/// Uploads the FFTs of the partitions of the impulse response of each channel.
+	fn new(device: &Arc<Device>, queue: &Arc<Queue>, responses: &[&[Vec<Complex<f32>>]]) -> Self {
+		let size = CONVOLUTION_BLOCK as u64 * 2;
+		let channels = responses.len();
+		let slots = responses[0].len();
+		let partitions: Vec<f32> = responses
+			.iter()
+			.flat_map(|response| response.iter().flatten())
+			.flat_map(|x| [x.re, x.im])
+			.collect();
+		let partitions = device.create_buffer_init(&util::BufferInitDescriptor {
+			label: None,
+			contents: bytemuck::cast_slice(&partitions),
+			usage: BufferUsages::STORAGE,
+		});
+		let buffer = |len: u64, usage| {
+			device.create_buffer(&BufferDescriptor {
+				label: None,
+				size: len * 8,
+				usage,
+				mapped_at_creation: false,
+			})
+		};
+		let history = buffer(
+			size * (channels * slots) as u64,
+			BufferUsages::STORAGE | BufferUsages::COPY_DST,
+		);
+		let work = BufferUsages::STORAGE | BufferUsages::COPY_SRC;
+		let buffers = [
+			buffer(size * channels as u64, work),
+			buffer(size * channels as u64, work),
+		];
+		let staging = buffer(
+			size * channels as u64,
+			BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+		);
+		let info = device.create_buffer(&BufferDescriptor {
+			label: None,
+			size: 16,
+			usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+			mapped_at_creation: false,
+		});
+		let entry = |binding, ty| BindGroupLayoutEntry {
+			binding,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+		let storage = BufferBindingType::Storage { read_only: false };
+		let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+			label: None,
+			entries: &[
+				entry(0, storage),
+				entry(1, storage),
+				entry(2, storage),
+				entry(3, BufferBindingType::Uniform),
+			],
+		});
+		let bind_group = device.create_bind_group(&BindGroupDescriptor {
+			label: None,
+			layout: &layout,
+			entries: &[&partitions, &history, &buffers[0], &info]
+				.iter()
+				.enumerate()
+				.map(|(binding, buffer)| BindGroupEntry {
+					binding: binding as u32,
+					resource: buffer.as_entire_binding(),
+				})
+				.collect::<Vec<_>>(),
+		});
+		let module = device.create_shader_module(&ShaderModuleDescriptor {
+			label: None,
+			source: ShaderSource::Wgsl(glsl_to_wgsl(PARTITIONS_SHADER).into()),
+		});
+		let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+			label: None,
+			bind_group_layouts: &[&layout],
+			push_constant_ranges: &[],
+		});
+		let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+			label: None,
+			layout: Some(&pipeline_layout),
+			module: &module,
+			entry_point: "main",
+		});
+		Self {
+			device: Arc::clone(device),
+			queue: Arc::clone(queue),
+			channels,
+			slots,
+			head: 0,
+			pipeline,
+			bind_group,
+			fft: GpuInverseFft::new(device),
+			_partitions: partitions,
+			history,
+			info,
+			buffers,
+			staging,
+		}
+	}
+
+	/// Appends the FFT of a complete block of each channel. Returns the output samples of
+	/// the next block of each channel convolved by the partitions.
+	fn accumulate(&mut self, spectra: &[Vec<Complex<f32>>]) -> Vec<Vec<f32>> {
+		let size = CONVOLUTION_BLOCK * 2;
+		self.head = (self.head + 1) % self.slots;
+		spectra.iter().enumerate().for_each(|(channel, spectrum)| {
+			let data: Vec<f32> = spectrum.iter().flat_map(|x| [x.re, x.im]).collect();
+			let offset = ((channel * self.slots + self.head) * size * 8) as u64;
+			self.queue
+				.write_buffer(&self.history, offset, bytemuck::cast_slice(&data));
+		});
+		let info = [size as u32, self.slots as u32, self.head as u32, 0];
+		self.queue
+			.write_buffer(&self.info, 0, bytemuck::cast_slice(&info));
+		let mut encoder = self.device.create_command_encoder(&Default::default());
+		{
+			let mut cpass = encoder.begin_compute_pass(&Default::default());
+			cpass.set_pipeline(&self.pipeline);
+			cpass.set_bind_group(0, &self.bind_group, &[]);
+			cpass.insert_debug_marker("convolution partitions");
+			cpass.dispatch(size as u32 / 64, self.channels as u32, 1);
+		}
+		let [sums, work] = &self.buffers;
+		let result = self.fft.encode(
+			&self.device,
+			&mut encoder,
+			[sums, work],
+			size as u32,
+			self.channels as u32,
+		);
+		encoder.copy_buffer_to_buffer(
+			&self.buffers[result],
+			0,
+			&self.staging,
+			0,
+			(size * self.channels * 8) as u64,
+		);
+		self.queue.submit(Some(encoder.finish()));
+
+		let buffer_slice = self.staging.slice(..);
+		let buffer_future = buffer_slice.map_async(MapMode::Read);
+		self.device.poll(Maintain::Wait);
+		let data: Vec<f32> = pollster::block_on(async {
+			if buffer_future.await.is_ok() {
+				let data = buffer_slice.get_mapped_range();
+				let result = bytemuck::cast_slice(&data).to_vec();
+				drop(data);
+				self.staging.unmap();
+				result
+			} else {
+				panic!("failed to run compute on gpu");
+			}
+		});
+		// the second half of the inverse FFT is the output, as the first partition.
+		let scale = 1.0 / size as f32;
+		data.chunks(size * 2)
+			.map(|sequence| {
+				sequence[size..]
+					.iter()
+					.step_by(2)
+					.map(|x| x * scale)
+					.collect()
+			})
+			.collect()
+	}
+}
+
+/// Interleaved samples convolved by `Convolver`, followed by the tail of the convolution
+/// after `samples` ends.
+pub(crate) struct ConvolvedSamples {
+	samples: Box<dyn Iterator<Item = f32> + Send>,
+	convolver: Convolver,
+	/// Frames of the tail to be returned
+	tail: usize,
+	queue: Vec<f32>,
+	cursor: usize,
+}
+
+impl ConvolvedSamples {
+	pub fn new(samples: Box<dyn Iterator<Item = f32> + Send>, convolver: Convolver) -> Self {
+		Self {
+			tail: convolver.len(),
+			samples,
+			convolver,
+			queue: Vec::new(),
+			cursor: 0,
+		}
+	}
+
+	/// Convolves next block in `queue`. Returns `false` at the end of the tail.
+	fn fill(&mut self) -> bool {
+		let channels = self.convolver.channels;
+		let mut block: Vec<f32> = self
+			.samples
+			.by_ref()
+			.take(CONVOLUTION_BLOCK * channels)
+			.collect();
+		block.truncate(block.len() - block.len() % channels);
+		if block.len() < CONVOLUTION_BLOCK * channels {
+			let frames = usize::min(CONVOLUTION_BLOCK - block.len() / channels, self.tail);
+			self.tail -= frames;
+			block.resize(block.len() + frames * channels, 0.0);
+		}
+		self.queue = self.convolver.process(&block);
+		self.cursor = 0;
+		!self.queue.is_empty()
+	}
+}
+
+impl Iterator for ConvolvedSamples {
+	type Item = f32;
+	fn next(&mut self) -> Option<f32> {
+		if self.cursor == self.queue.len() && !self.fill() {
+			return None;
+		}
+		self.cursor += 1;
+		Some(self.queue[self.cursor - 1])
+	}
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let queued = self.queue.len() - self.cursor;
+		let (lower, upper) = self.samples.size_hint();
+		let tail = self.tail * self.convolver.channels;
+		(
+			queued + lower + tail,
+			upper.map(|upper| queued + upper + tail),
+		)
+	}
+}
+
+#[test]
+fn glsl_to_wgsl_partitions_test() {
+	let code = glsl_to_wgsl(PARTITIONS_SHADER);
+	println!("{}", code);
+}
+
+#[test]
+fn convolver_test() {
+	let (device, queue) = crate::wgpu_wrapper::init_device();
+	let (device, queue) = (Arc::new(device), Arc::new(queue));
+	// an echo after 1500 frames, longer than a partition
+	let mut impulse_response = vec![0.0; 1501];
+	impulse_response[0] = 1.0;
+	impulse_response[1500] = 0.5;
+	let mut convolver = Convolver::new(&[impulse_response], 2, 1.0, 0.5, &device, &queue);
+	let samples: Vec<f32> = (0..5000)
+		.flat_map(|i| {
+			let x = f32::sin(i as f32 * 0.01);
+			vec![x, -x]
+		})
+		.collect();
+	// chunks which are not aligned with the blocks
+	let res: Vec<f32> = samples
+		.chunks(333 * 2)
+		.flat_map(|chunk| convolver.process(chunk))
+		.collect();
+	assert_eq!(res.len(), samples.len());
+	res.iter().enumerate().for_each(|(i, x)| {
+		let echo = samples.get(i.wrapping_sub(3000)).copied().unwrap_or(0.0);
+		let answer = samples[i] * 1.5 + echo * 0.5;
+		assert!(f32::abs(x - answer) < 1.0e-4, "{} {} {}", i, x, answer);
+	});
+
+	let convolver = Convolver::new(&[vec![0.0, 1.0]], 1, 1.0, 0.0, &device, &queue);
+	let samples = Box::new(vec![1.0, 2.0, 3.0].into_iter());
+	let res: Vec<f32> = ConvolvedSamples::new(samples, convolver).collect();
+	assert!(
+		res.len() == 5 && f32::abs(res[3] - 3.0) < 1.0e-6,
+		"{:?}",
+		res
+	);
+}
@@ -0,0 +1,516 @@
+use flacenc::bitsink::ByteSink;
+use flacenc::component::{BitRepr, Stream, StreamInfo};
+use flacenc::constant::MIN_BLOCK_SIZE;
+use flacenc::error::{Verified, Verify};
+use flacenc::source::{Fill, FrameBuf};
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::num::{NonZeroU32, NonZeroU8};
+use std::path::Path;
+
+/// Format of recorded audio files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+	/// 16-bit integer PCM WAV
+	Wav16,
+	/// 24-bit integer PCM WAV
+	Wav24,
+	/// 32-bit float WAV
+	Wav32F,
+	/// 24-bit FLAC
+	Flac,
+	/// Ogg Vorbis
+	Ogg,
+}
+
+impl OutputFormat {
+	/// Guesses the format from the extension of `path`. Falls back to `Wav32F`.
+	pub fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+		let extension = path
+			.as_ref()
+			.extension()
+			.and_then(|ext| ext.to_str())
+			.map(str::to_lowercase);
+		match extension.as_deref() {
+			Some("flac") => Self::Flac,
+			Some("ogg") | Some("oga") => Self::Ogg,
+			_ => Self::Wav32F,
+		}
+	}
+
+	/// Returns the bit depth of the quantized samples, `None` for floating point formats.
+	pub fn bits_per_sample(self) -> Option<u16> {
+		match self {
+			Self::Wav16 => Some(16),
+			Self::Wav24 | Self::Flac => Some(24),
+			Self::Wav32F | Self::Ogg => None,
+		}
+	}
+}
+
+impl std::str::FromStr for OutputFormat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, String> {
+		match s {
+			"wav16" => Ok(Self::Wav16),
+			"wav24" => Ok(Self::Wav24),
+			"wav32f" => Ok(Self::Wav32F),
+			"flac" => Ok(Self::Flac),
+			"ogg" => Ok(Self::Ogg),
+			_ => Err(format!(
+				"unknown output format: {}\nsupported: wav16, wav24, wav32f, flac, ogg",
+				s
+			)),
+		}
+	}
+}
+
+/// Small xorshift generator for dither noise
+#[derive(Clone, Debug)]
+struct XorShift32(u32);
+
+impl XorShift32 {
+	/// Uniform random number in `[-0.5, 0.5)`
+	fn next_f32(&mut self) -> f32 {
+		let mut x = self.0;
+		x ^= x << 13;
+		x ^= x >> 17;
+		x ^= x << 5;
+		self.0 = x;
+		(x >> 8) as f32 / (1 << 24) as f32 - 0.5
+	}
+}
+
+/// Quantizer to integer samples with TPDF dither and optional first-order noise shaping
+#[derive(Clone, Debug)]
+pub struct Dither {
+	scale: f32,
+	noise_shaping: bool,
+	errors: Vec<f32>,
+	rng: XorShift32,
+}
+
+impl Dither {
+	/// Creates a quantizer for `bits_per_sample` and interleaved `channels`.
+	pub fn new(bits_per_sample: u16, channels: usize, noise_shaping: bool) -> Self {
+		Self {
+			scale: f32::powi(2.0, bits_per_sample as i32 - 1),
+			noise_shaping,
+			errors: vec![0.0; channels],
+			rng: XorShift32(0x9E37_79B9),
+		}
+	}
+
+	/// Quantizes interleaved samples.
+	pub fn quantize(&mut self, samples: &[f32]) -> Vec<i32> {
+		let Self {
+			scale,
+			noise_shaping,
+			errors,
+			rng,
+		} = self;
+		let (min, max) = (-*scale, *scale - 1.0);
+		let channels = errors.len();
+		samples
+			.iter()
+			.enumerate()
+			.map(|(i, x)| {
+				let error = &mut errors[i % channels];
+				let target = x * *scale - *error;
+				let noise = rng.next_f32() + rng.next_f32();
+				let quantized = f32::round(target + noise).clamp(min, max);
+				if *noise_shaping {
+					// bounded so that clipped samples do not blow up the feedback
+					*error = (quantized - target).clamp(-2.0, 2.0);
+				}
+				quantized as i32
+			})
+			.collect()
+	}
+}
+
+#[test]
+fn dither_test() {
+	let mut dither = Dither::new(16, 2, true);
+	let samples: Vec<f32> = (0..44100)
+		.map(|i| 0.5 * f32::sin(i as f32 * 0.01))
+		.collect();
+	let quantized = dither.quantize(&samples);
+	samples.iter().zip(quantized).for_each(|(x, q)| {
+		assert!(
+			f32::abs(x * 32768.0 - q as f32) < 4.0,
+			"{} {}",
+			x * 32768.0,
+			q
+		);
+	});
+	let clipped = Dither::new(16, 1, false).quantize(&[1.5, -1.5]);
+	assert_eq!(clipped, vec![32767, -32768]);
+}
+
+enum WriterInner {
+	Wav(hound::WavWriter<BufWriter<File>>),
+	Flac(Box<FlacWriter>),
+	// Ogg pages are written as soon as they are completed, so the file is not buffered.
+	Ogg(Box<vorbis_rs::VorbisEncoder<File>>),
+}
+
+/// Writer of stereo audio files
+pub struct AudioFileWriter {
+	inner: WriterInner,
+	dither: Option<Dither>,
+}
+
+impl AudioFileWriter {
+	/// Creates an audio file. `noise_shaping` is ignored by floating point formats.
+	pub fn create<P: AsRef<Path>>(
+		path: P,
+		format: OutputFormat,
+		sample_rate: u32,
+		noise_shaping: bool,
+	) -> Result<Self, String> {
+		use hound::*;
+		let path = path.as_ref();
+		let inner = match format {
+			OutputFormat::Wav16 | OutputFormat::Wav24 | OutputFormat::Wav32F => {
+				let spec = WavSpec {
+					channels: 2,
+					sample_rate,
+					bits_per_sample: format.bits_per_sample().unwrap_or(32),
+					sample_format: match format {
+						OutputFormat::Wav32F => SampleFormat::Float,
+						_ => SampleFormat::Int,
+					},
+				};
+				let writer = WavWriter::create(path, spec).map_err(|e| format!("{}", e))?;
+				WriterInner::Wav(writer)
+			}
+			OutputFormat::Flac => {
+				WriterInner::Flac(Box::new(FlacWriter::create(path, sample_rate)?))
+			}
+			OutputFormat::Ogg => {
+				let file = File::create(path).map_err(|e| format!("{}", e))?;
+				let encoder = vorbis_rs::VorbisEncoderBuilder::new(
+					NonZeroU32::new(sample_rate).ok_or("sample rate must not be zero")?,
+					NonZeroU8::new(2).unwrap(),
+					file,
+				)
+				.map_err(|e| format!("{}", e))?
+				.build()
+				.map_err(|e| format!("{}", e))?;
+				WriterInner::Ogg(Box::new(encoder))
+			}
+		};
+		let dither = format
+			.bits_per_sample()
+			.map(|bits| Dither::new(bits, 2, noise_shaping));
+		Ok(Self { inner, dither })
+	}
+
+	/// Writes interleaved stereo samples.
+	pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+		match (&mut self.inner, &mut self.dither) {
+			(WriterInner::Wav(writer), Some(dither)) => dither
+				.quantize(samples)
+				.into_iter()
+				.try_for_each(|s| writer.write_sample(s))
+				.map_err(|e| format!("{}", e)),
+			(WriterInner::Wav(writer), None) => samples
+				.iter()
+				.try_for_each(|s| writer.write_sample(*s))
+				.map_err(|e| format!("{}", e)),
+			(WriterInner::Flac(writer), Some(dither)) => writer.write(&dither.quantize(samples)),
+			(WriterInner::Flac(_), None) => unreachable!(),
+			(WriterInner::Ogg(encoder), _) => {
+				let left: Vec<f32> = samples.iter().step_by(2).copied().collect();
+				let right: Vec<f32> = samples.iter().skip(1).step_by(2).copied().collect();
+				encoder
+					.encode_audio_block(&[left, right])
+					.map_err(|e| format!("{}", e))
+			}
+		}
+	}
+
+	/// Flushes written samples to the file. The headers of WAV and FLAC are updated so that the
+	/// partial file is valid. Ogg pages are written as soon as they are completed, so the samples
+	/// of the incomplete page are not written here.
+	pub fn flush(&mut self) -> Result<(), String> {
+		match &mut self.inner {
+			WriterInner::Wav(writer) => writer.flush().map_err(|e| format!("{}", e)),
+			WriterInner::Flac(writer) => writer.flush(),
+			WriterInner::Ogg(_) => Ok(()),
+		}
+	}
+
+	/// Flushes the buffered samples and completes the file.
+	pub fn finalize(self) -> Result<(), String> {
+		match self.inner {
+			WriterInner::Wav(writer) => writer.finalize().map_err(|e| format!("{}", e)),
+			WriterInner::Flac(writer) => writer.finalize(),
+			WriterInner::Ogg(encoder) => encoder.finish().map(drop).map_err(|e| format!("{}", e)),
+		}
+	}
+}
+
+/// FLAC encoder of 24-bit stereo samples, which writes each block as soon as it is filled.
+///
+/// The stream info at the beginning of the file is rewritten by `flush`, so that the partial
+/// file is valid. The MD5 signature of the samples is left unset.
+struct FlacWriter {
+	file: BufWriter<File>,
+	config: Verified<flacenc::config::Encoder>,
+	/// Encoder of the last block shorter than `MIN_BLOCK_SIZE`, which is stored verbatim since
+	/// the predictors of `config` need longer blocks
+	verbatim_config: Verified<flacenc::config::Encoder>,
+	stream_info: StreamInfo,
+	framebuf: FrameBuf,
+	/// Interleaved samples of the incomplete block
+	pending: Vec<i32>,
+	/// The number of written frames of FLAC
+	frame_number: usize,
+	/// The number of written samples per channel
+	total_samples: usize,
+}
+
+impl FlacWriter {
+	fn create(path: &Path, sample_rate: u32) -> Result<Self, String> {
+		let config = flacenc::config::Encoder::default()
+			.into_verified()
+			.map_err(|e| format!("{:?}", e))?;
+		let mut verbatim_config = flacenc::config::Encoder::default();
+		verbatim_config.subframe_coding.use_fixed = false;
+		verbatim_config.subframe_coding.use_lpc = false;
+		let verbatim_config = verbatim_config
+			.into_verified()
+			.map_err(|e| format!("{:?}", e))?;
+		let stream_info =
+			StreamInfo::new(sample_rate as usize, 2, 24).map_err(|e| format!("{:?}", e))?;
+		let framebuf = FrameBuf::with_size(2, config.block_size).map_err(|e| format!("{:?}", e))?;
+		let file = File::create(path).map_err(|e| format!("{}", e))?;
+		let mut writer = Self {
+			file: BufWriter::new(file),
+			config,
+			verbatim_config,
+			stream_info,
+			framebuf,
+			pending: Vec::new(),
+			frame_number: 0,
+			total_samples: 0,
+		};
+		writer.write_header()?;
+		Ok(writer)
+	}
+
+	/// Writes the stream marker and the stream info at the beginning of the file.
+	fn write_header(&mut self) -> Result<(), String> {
+		let mut stream_info = self.stream_info.clone();
+		// the last block is excluded from the minimum block size.
+		let block_size = self.config.block_size;
+		stream_info
+			.set_block_sizes(block_size, block_size)
+			.map_err(|e| format!("{:?}", e))?;
+		if self.frame_number == 0 {
+			// unknown
+			stream_info
+				.set_frame_sizes(0, 0)
+				.map_err(|e| format!("{:?}", e))?;
+		}
+		stream_info.set_total_samples(self.total_samples);
+		let mut sink = ByteSink::new();
+		Stream::with_stream_info(stream_info)
+			.write(&mut sink)
+			.map_err(|e| format!("{:?}", e))?;
+		self.file
+			.seek(SeekFrom::Start(0))
+			.and_then(|_| self.file.write_all(sink.as_slice()))
+			.and_then(|_| self.file.seek(SeekFrom::End(0)))
+			.map(drop)
+			.map_err(|e| format!("{}", e))
+	}
+
+	/// Encodes interleaved samples of a block. Only the last block may be shorter than the block
+	/// size.
+	fn write_frame(&mut self, samples: &[i32]) -> Result<(), String> {
+		let frames = samples.len() / 2;
+		self.framebuf.resize(frames);
+		self.framebuf
+			.fill_interleaved(samples)
+			.map_err(|e| format!("{:?}", e))?;
+		let config = if frames < MIN_BLOCK_SIZE {
+			&self.verbatim_config
+		} else {
+			&self.config
+		};
+		let frame = flacenc::encode_fixed_size_frame(
+			config,
+			&self.framebuf,
+			self.frame_number,
+			&self.stream_info,
+		)
+		.map_err(|e| format!("{:?}", e))?;
+		self.stream_info.update_frame_info(&frame);
+		let mut sink = ByteSink::new();
+		frame.write(&mut sink).map_err(|e| format!("{:?}", e))?;
+		self.file
+			.write_all(sink.as_slice())
+			.map_err(|e| format!("{}", e))?;
+		self.frame_number += 1;
+		self.total_samples += frames;
+		Ok(())
+	}
+
+	/// Appends quantized interleaved samples, and encodes the filled blocks.
+	fn write(&mut self, samples: &[i32]) -> Result<(), String> {
+		self.pending.extend_from_slice(samples);
+		let len = self.config.block_size * 2;
+		while self.pending.len() >= len {
+			let block: Vec<i32> = self.pending.drain(..len).collect();
+			self.write_frame(&block)?;
+		}
+		Ok(())
+	}
+
+	fn flush(&mut self) -> Result<(), String> {
+		self.write_header()?;
+		self.file.flush().map_err(|e| format!("{}", e))
+	}
+
+	/// Encodes the incomplete block and completes the file.
+	fn finalize(mut self) -> Result<(), String> {
+		if !self.pending.is_empty() {
+			let block = std::mem::take(&mut self.pending);
+			self.write_frame(&block)?;
+		}
+		self.flush()
+	}
+}
+
+#[test]
+fn writer_test() {
+	use hound::{SampleFormat, WavReader};
+	let dir = std::env::temp_dir().join("sound-shader-writer-test");
+	std::fs::create_dir_all(&dir).unwrap();
+	let samples: Vec<f32> = (0..44100)
+		.flat_map(|i| {
+			let t = 2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 44100.0;
+			vec![f32::cos(t) * 0.5, f32::sin(t) * 0.5]
+		})
+		.collect();
+	let formats = [
+		(OutputFormat::Wav16, "wav", 2.0 / 32768.0),
+		(OutputFormat::Wav24, "wav", 2.0 / 8388608.0),
+		(OutputFormat::Wav32F, "wav", 0.0),
+		(OutputFormat::Flac, "flac", 2.0 / 8388608.0),
+		(OutputFormat::Ogg, "ogg", 0.02),
+	];
+	for (format, extension, tolerance) in formats {
+		let path = dir.join(format!("{:?}.{}", format, extension));
+		let mut writer = AudioFileWriter::create(&path, format, 44100, false).unwrap();
+		samples
+			.chunks(1000 * 2)
+			.for_each(|chunk| writer.write(chunk).unwrap());
+		writer.finalize().unwrap();
+
+		let (decoded, spec): (Vec<f32>, _) = match format {
+			OutputFormat::Flac | OutputFormat::Ogg => {
+				let magic = if format == OutputFormat::Flac {
+					b"fLaC"
+				} else {
+					b"OggS"
+				};
+				assert_eq!(&std::fs::read(&path).unwrap()[..4], magic, "{:?}", format);
+				let (samples, spec) = crate::symphonia_wrapper::open_samples(&path).unwrap();
+				(samples.collect(), spec)
+			}
+			_ => {
+				let spec = WavReader::open(&path).unwrap().spec();
+				assert_eq!(spec.bits_per_sample, format.bits_per_sample().unwrap_or(32));
+				let sample_format = match format {
+					OutputFormat::Wav32F => SampleFormat::Float,
+					_ => SampleFormat::Int,
+				};
+				assert_eq!(spec.sample_format, sample_format);
+				let (samples, spec) = crate::hound_wrapper::open_samples(&path).unwrap();
+				(samples.collect(), spec)
+			}
+		};
+		assert_eq!(
+			(spec.sample_rate, spec.channels),
+			(44100, 2),
+			"{:?}",
+			format
+		);
+		assert_eq!(decoded.len(), samples.len(), "{:?}", format);
+		samples
+			.iter()
+			.zip(&decoded)
+			.enumerate()
+			.for_each(|(i, (x, y))| {
+				assert!(
+					f32::abs(x - y) <= tolerance,
+					"{:?} {} {} {}",
+					format,
+					i,
+					x,
+					y
+				);
+			});
+	}
+}
+
+#[test]
+fn partial_file_test() {
+	let dir = std::env::temp_dir().join("sound-shader-partial-file-test");
+	std::fs::create_dir_all(&dir).unwrap();
+	let samples: Vec<f32> = (0..44100 * 2).map(|i| (i % 100) as f32 / 200.0).collect();
+	for (format, extension) in [(OutputFormat::Wav16, "wav"), (OutputFormat::Flac, "flac")] {
+		let path = dir.join(format!("{:?}.{}", format, extension));
+		let mut writer = AudioFileWriter::create(&path, format, 44100, false).unwrap();
+		writer.write(&samples).unwrap();
+		writer.flush().unwrap();
+		// the file is read before `finalize`, as if the process were killed.
+		let (len, spec) = match format {
+			OutputFormat::Flac => {
+				let (decoded, spec) = crate::symphonia_wrapper::open_samples(&path).unwrap();
+				(decoded.count(), spec)
+			}
+			_ => {
+				let (decoded, spec) = crate::hound_wrapper::open_samples(&path).unwrap();
+				(decoded.count(), spec)
+			}
+		};
+		assert_eq!(
+			(spec.sample_rate, spec.channels),
+			(44100, 2),
+			"{:?}",
+			format
+		);
+		// FLAC writes only the complete blocks.
+		let answer = match format {
+			OutputFormat::Flac => 44100 / 4096 * 4096 * 2,
+			_ => samples.len(),
+		};
+		assert_eq!(len, answer, "{:?}", format);
+		writer.finalize().unwrap();
+	}
+}
+
+#[test]
+fn flac_last_block_test() {
+	let dir = std::env::temp_dir().join("sound-shader-flac-last-block-test");
+	std::fs::create_dir_all(&dir).unwrap();
+	let path = dir.join("last-block.flac");
+	// the last block of 10 frames is shorter than the minimum block size of FLAC.
+	let frames = 4096 + 10;
+	let samples: Vec<f32> = (0..frames * 2).map(|i| (i % 100) as f32 / 200.0).collect();
+	let mut writer = AudioFileWriter::create(&path, OutputFormat::Flac, 44100, false).unwrap();
+	writer.write(&samples).unwrap();
+	writer.finalize().unwrap();
+	let (decoded, _) = crate::symphonia_wrapper::open_samples(&path).unwrap();
+	let decoded: Vec<f32> = decoded.collect();
+	assert_eq!(decoded.len(), samples.len());
+	samples.iter().zip(&decoded).for_each(|(x, y)| {
+		assert!(f32::abs(x - y) <= 2.0 / 8388608.0, "{} {}", x, y);
+	});
+}
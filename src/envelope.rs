@@ -0,0 +1,243 @@
+use crate::options::{StftOptions, Window};
+use crate::stft::StftRows;
+use std::collections::VecDeque;
+
+/// Frames between adjacent analyses
+pub(crate) const ENVELOPE_HOP: usize = 256;
+/// Frames of each analysis
+const ENVELOPE_SIZE: usize = 1024;
+/// Ratio of the onset strength of a beat to the recent mean
+const BEAT_THRESHOLD: f32 = 1.5;
+/// Minimum onset strength of a beat
+const BEAT_MIN_ONSET: f32 = 0.01;
+/// The number of frames of the recent mean of onset strength
+const BEAT_HISTORY: usize = 64;
+/// Minimum seconds between beats
+const BEAT_MIN_INTERVAL: f32 = 0.1;
+
+/// RMS envelope, onset strength and beats of the mean of the first two channels.
+///
+/// The onset strength is the spectral flux: the mean increase of the log-compressed magnitudes
+/// of the bins from the previous frame. Beats are the peaks of the onset strength above
+/// the recent mean. The analysis is causal without lookahead, so that it can run incrementally
+/// on the samples reserved ahead of playback by `EnvelopeTrack`.
+pub(crate) struct EnvelopeAnalyser {
+	stft: StftRows<[f32; 2]>,
+	sample_rate: u32,
+	/// Log-compressed magnitudes of the last frame
+	magnitudes: Vec<f32>,
+	/// Onset strengths of the recent frames, the newest at the back
+	onsets: VecDeque<f32>,
+	/// `[seconds, onset strength]` of the last beat
+	last_beat: Option<[f32; 2]>,
+}
+
+impl EnvelopeAnalyser {
+	pub fn new(sample_rate: u32, channels: usize) -> Self {
+		let options = StftOptions {
+			size: ENVELOPE_SIZE,
+			hop: ENVELOPE_HOP,
+			window: Window::Hann,
+		};
+		let stft = StftRows::new(&options, channels);
+		Self {
+			magnitudes: vec![0.0; stft.stft().bins()],
+			stft,
+			sample_rate,
+			onsets: VecDeque::new(),
+			last_beat: None,
+		}
+	}
+
+	/// Appends interleaved samples. Returns the index of the first frame,
+	/// `[envelope, onset strength]` of each frame as `Stft::process`, and `[seconds, onset strength]`
+	/// of the beats detected. A beat is detected at the frame after its peak.
+	pub fn process(&mut self, samples: &[f32]) -> (usize, Vec<[f32; 2]>, Vec<[f32; 2]>) {
+		let Self {
+			stft,
+			sample_rate,
+			magnitudes,
+			onsets,
+			last_beat,
+		} = self;
+		let bins = stft.stft().bins();
+		let mut beats = Vec::new();
+		let (first, rows) = stft.process(samples, |frame, frame_samples, left, right, rows| {
+			let channels = frame_samples.len() / ENVELOPE_SIZE;
+			let energy = frame_samples
+				.chunks(channels)
+				.map(|x| {
+					let sample = (x[0] + x[usize::min(1, channels - 1)]) * 0.5;
+					sample * sample
+				})
+				.sum::<f32>();
+			let envelope = f32::sqrt(energy / ENVELOPE_SIZE as f32);
+			let mut flux = 0.0;
+			magnitudes
+				.iter_mut()
+				.enumerate()
+				.for_each(|(i, magnitude)| {
+					// the amplitude of sine waves
+					let amplitude = (left[i] + right[i]).norm() * 2.0 / ENVELOPE_SIZE as f32;
+					let compressed = f32::ln_1p(100.0 * amplitude);
+					flux += f32::max(compressed - *magnitude, 0.0);
+					*magnitude = compressed;
+				});
+			let onset = flux / bins as f32;
+			// the previous frame is a beat if it is a peak above the recent mean.
+			if onsets.len() >= 2 {
+				let peak = onsets[onsets.len() - 1];
+				let history = onsets.len() - 1;
+				let mean = onsets.iter().take(history).sum::<f32>() / history as f32;
+				let is_peak = peak > onsets[history - 1] && peak >= onset;
+				let time = (frame - 1) as f32 * ENVELOPE_HOP as f32 / *sample_rate as f32;
+				// the transient has entered the window a while before the end of the frame.
+				let time = f32::max(time - (ENVELOPE_SIZE / 4) as f32 / *sample_rate as f32, 0.0);
+				let interval = last_beat.map_or(f32::INFINITY, |beat| time - beat[0]);
+				if is_peak
+					&& peak > mean * BEAT_THRESHOLD + BEAT_MIN_ONSET
+					&& interval >= BEAT_MIN_INTERVAL
+				{
+					*last_beat = Some([time, peak]);
+					beats.push([time, peak]);
+				}
+			}
+			onsets.push_back(onset);
+			if onsets.len() > BEAT_HISTORY + 1 {
+				onsets.pop_front();
+			}
+			rows.push([envelope, onset]);
+		});
+		(first, rows, beats)
+	}
+}
+
+/// Envelope rows and beats of a resource, analysed ahead of the played block as far as the
+/// samples are available.
+pub(crate) struct EnvelopeTrack {
+	analyser: EnvelopeAnalyser,
+	/// Frames of the resource analysed
+	analysed: usize,
+	/// The index of the first row of `rows`
+	base_frame: usize,
+	/// `[envelope, onset strength]` of the frames analysed from `base_frame`
+	rows: Vec<[f32; 2]>,
+	/// `[seconds, onset strength]` of the beats analysed, from the last one before `base_frame`
+	beats: Vec<[f32; 2]>,
+}
+
+impl EnvelopeTrack {
+	pub fn new(sample_rate: u32, channels: usize) -> Self {
+		Self {
+			analyser: EnvelopeAnalyser::new(sample_rate, channels),
+			analysed: 0,
+			base_frame: 0,
+			rows: Vec::new(),
+			beats: Vec::new(),
+		}
+	}
+
+	/// Frames of the resource analysed
+	pub fn analysed(&self) -> usize {
+		self.analysed
+	}
+
+	/// Analyses interleaved samples of the frames from `analysed`.
+	pub fn append(&mut self, samples: &[f32]) {
+		let channels = self.analyser.stft.stft().channels();
+		let frames = samples.len() / channels;
+		if frames == 0 {
+			return;
+		}
+		let (first, rows, beats) = self.analyser.process(&samples[..frames * channels]);
+		// the last frame of the previous call is returned again, and replaces its row.
+		self.rows.truncate(first.saturating_sub(self.base_frame));
+		let skip = usize::min(self.base_frame.saturating_sub(first), rows.len());
+		self.rows.extend_from_slice(&rows[skip..]);
+		self.beats.extend(beats);
+		self.analysed += frames;
+	}
+
+	/// Drops the rows before the frame of the resource `frame`, and the beats before it except
+	/// the last one. Returns the index of the first row, the rows and the beats.
+	pub fn rows_from(&mut self, frame: usize) -> (usize, &[[f32; 2]], &[[f32; 2]]) {
+		let base_frame = frame / ENVELOPE_HOP;
+		if base_frame > self.base_frame {
+			let drop = usize::min(base_frame - self.base_frame, self.rows.len());
+			self.rows.drain(..drop);
+			self.base_frame = base_frame;
+		}
+		let time = frame as f32 / self.analyser.sample_rate as f32;
+		let passed = self.beats.iter().take_while(|beat| beat[0] <= time).count();
+		self.beats.drain(..passed.saturating_sub(1));
+		(self.base_frame, &self.rows, &self.beats)
+	}
+}
+
+#[test]
+fn envelope_test() {
+	let sample_rate = 44100;
+	let mut analyser = EnvelopeAnalyser::new(sample_rate, 1);
+	// bursts of noise every 0.5 seconds
+	let mut seed = 1u32;
+	let samples: Vec<f32> = (0..sample_rate as usize * 3)
+		.map(|i| {
+			seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+			let noise = (seed >> 16) as f32 / 32768.0 - 1.0;
+			let t = i % (sample_rate as usize / 2);
+			noise * f32::exp(-(t as f32) / 500.0)
+		})
+		.collect();
+	let mut beats = Vec::new();
+	let mut rows = Vec::new();
+	samples.chunks(10000).for_each(|block| {
+		let (first, res, res_beats) = analyser.process(block);
+		// the last frame of the previous call is returned again.
+		let skip = rows.len() - first;
+		rows.extend_from_slice(&res[skip..]);
+		beats.extend(res_beats);
+	});
+	assert_eq!(rows.len(), samples.len() / ENVELOPE_HOP + 1);
+	assert!(rows[10][0] > 0.01 && rows[80][0] < 0.001);
+	let times: Vec<f32> = beats.iter().map(|beat| beat[0]).collect();
+	assert_eq!(times.len(), 6, "{:?}", times);
+	times.iter().enumerate().for_each(|(i, time)| {
+		let answer = 0.5 * i as f32;
+		assert!(f32::abs(time - answer) < 0.015, "{:?}", times);
+	});
+	assert_eq!(
+		analyser.last_beat.map(|beat| beat[0]),
+		times.last().copied()
+	);
+}
+
+#[test]
+fn envelope_track_test() {
+	let sample_rate = 44100;
+	let mut track = EnvelopeTrack::new(sample_rate, 2);
+	// clicks at 0.25 and 0.75 seconds
+	let samples: Vec<f32> = (0..sample_rate as usize)
+		.flat_map(|i| {
+			let t = (i + sample_rate as usize / 4) % (sample_rate as usize / 2);
+			let x = if t < 100 { 0.8 } else { 0.0 };
+			vec![x, x]
+		})
+		.collect();
+	samples.chunks(3000).for_each(|chunk| track.append(chunk));
+	assert_eq!(track.analysed(), sample_rate as usize);
+	// the beats after the frame are known in advance.
+	let (base_frame, rows, beats) = track.rows_from(sample_rate as usize / 10);
+	assert_eq!(base_frame, sample_rate as usize / 10 / ENVELOPE_HOP);
+	assert_eq!(
+		rows.len(),
+		sample_rate as usize / ENVELOPE_HOP + 1 - base_frame
+	);
+	let times: Vec<f32> = beats.iter().map(|beat| beat[0]).collect();
+	assert_eq!(times.len(), 2, "{:?}", times);
+	assert!(f32::abs(times[0] - 0.25) < 0.015 && f32::abs(times[1] - 0.75) < 0.015);
+	// the last beat before the frame is kept.
+	let (_, _, beats) = track.rows_from(sample_rate as usize / 2);
+	assert_eq!(beats.len(), 2);
+	let (_, _, beats) = track.rows_from(sample_rate as usize * 9 / 10);
+	assert_eq!(beats.len(), 1);
+}
@@ -0,0 +1,112 @@
+use crate::options::{StftOptions, Window};
+use crate::stft::Stft;
+
+/// FFT size of the spectrum of the output
+pub(crate) const FEEDBACK_FFT_SIZE: usize = 2048;
+
+/// Analysis of the rendered output, which is fed back to the next render call.
+pub(crate) struct FeedbackAnalyser {
+	stft: Stft,
+	/// The next STFT frame to be analysed
+	next_frame: usize,
+	/// Amplitudes of the bins `0..=FEEDBACK_FFT_SIZE / 2` of left and right channels
+	spectrum: Vec<[f32; 2]>,
+	/// RMS of left and right channels
+	rms: [f32; 2],
+}
+
+impl FeedbackAnalyser {
+	pub fn new() -> Self {
+		let options = StftOptions {
+			size: FEEDBACK_FFT_SIZE,
+			hop: FEEDBACK_FFT_SIZE / 2,
+			window: Window::Hann,
+		};
+		let stft = Stft::new(&options, 2);
+		Self {
+			spectrum: vec![[0.0; 2]; stft.bins()],
+			stft,
+			next_frame: 0,
+			rms: [0.0; 2],
+		}
+	}
+
+	/// Returns the amplitudes of the bins of left and right channels.
+	pub fn spectrum(&self) -> &[[f32; 2]] {
+		&self.spectrum
+	}
+
+	/// Returns RMS of left and right channels.
+	pub fn rms(&self) -> [f32; 2] {
+		self.rms
+	}
+
+	/// Analyses a chunk of interleaved stereo samples. The spectrum is the mean of the STFT
+	/// frames ending in the chunk, and is kept if no frames end in the chunk.
+	pub fn analyse(&mut self, samples: &[f32]) {
+		let frames = samples.len() / 2;
+		if frames == 0 {
+			return;
+		}
+		let mut sum = [0.0; 2];
+		samples.chunks_exact(2).for_each(|x| {
+			sum[0] += x[0] * x[0];
+			sum[1] += x[1] * x[1];
+		});
+		self.rms = [
+			f32::sqrt(sum[0] / frames as f32),
+			f32::sqrt(sum[1] / frames as f32),
+		];
+		let Self {
+			stft, next_frame, ..
+		} = self;
+		let bins = stft.bins();
+		let mut spectrum = vec![[0.0; 2]; bins];
+		let mut count = 0;
+		stft.process_frames(&samples[..frames * 2], |frame, _, left, right| {
+			// the last frame of the previous chunk is returned again.
+			if frame < *next_frame {
+				return;
+			}
+			*next_frame = frame + 1;
+			count += 1;
+			spectrum.iter_mut().enumerate().for_each(|(i, x)| {
+				x[0] += left[i].norm();
+				x[1] += right[i].norm();
+			});
+		});
+		if count > 0 {
+			// the sum of the Hann window is a half of its size, and the bins except DC and
+			// Nyquist have a half of the amplitude.
+			spectrum.iter_mut().enumerate().for_each(|(i, x)| {
+				let sides = if i == 0 || i == bins - 1 { 1.0 } else { 2.0 };
+				let scale = sides * 2.0 / (FEEDBACK_FFT_SIZE as f32 * count as f32);
+				*x = [x[0] * scale, x[1] * scale];
+			});
+			self.spectrum = spectrum;
+		}
+	}
+}
+
+#[test]
+fn feedback_test() {
+	let mut analyser = FeedbackAnalyser::new();
+	let samples: Vec<f32> = (0..FEEDBACK_FFT_SIZE * 8)
+		.flat_map(|i| {
+			let t = 2.0 * std::f32::consts::PI * 64.0 * i as f32 / FEEDBACK_FFT_SIZE as f32;
+			vec![f32::sin(t), 0.5]
+		})
+		.collect();
+	analyser.analyse(&samples[..FEEDBACK_FFT_SIZE * 2]);
+	analyser.analyse(&samples[FEEDBACK_FFT_SIZE * 2..]);
+	let rms = analyser.rms();
+	assert!(f32::abs(rms[0] - f32::sqrt(0.5)) < 0.001 && f32::abs(rms[1] - 0.5) < 0.001);
+	let spectrum = analyser.spectrum();
+	assert!(
+		f32::abs(spectrum[64][0] - 1.0) < 0.001,
+		"{:?}",
+		spectrum[64]
+	);
+	assert!(spectrum[32][0] < 0.001 && spectrum[96][0] < 0.001);
+	assert!(f32::abs(spectrum[0][1] - 0.5) < 0.001, "{:?}", spectrum[0]);
+}
@@ -0,0 +1,148 @@
+use crate::wgpu_wrapper::glsl_to_wgsl;
+use wgpu::{util::DeviceExt, *};
+
+/// A stage of the radix-2 Stockham inverse FFT. Each invocation computes a butterfly
+/// of the sequence `gl_GlobalInvocationID.y`.
+const INVERSE_FFT_SHADER: &str = "#version 450
+layout(local_size_x = 1) in;
+
+layout(set = 0, binding = 0) buffer Source {
+	vec2[] src;
+};
+
+layout(set = 0, binding = 1) buffer Destination {
+	vec2[] dst;
+};
+
+layout(set = 0, binding = 2) uniform Stage {
+	uint size;
+	uint stride;
+};
+
+void main() {
+	uint j = gl_GlobalInvocationID.x;
+	uint base = gl_GlobalInvocationID.y * size;
+	vec2 v0 = src[base + j];
+	vec2 v1 = src[base + j + size / 2u];
+	float angle = 3.14159265359 * float(j % stride) / float(stride);
+	float c = cos(angle);
+	float s = sin(angle);
+	v1 = vec2(v1.x * c - v1.y * s, v1.x * s + v1.y * c);
+	uint idx = base + (j / stride) * stride * 2u + j % stride;
+	dst[idx] = v0 + v1;
+	dst[idx + stride] = v0 - v1;
+}
+";
+
+/// Inverse FFT of sequences of complex values on GPU.
+pub(crate) struct GpuInverseFft {
+	layout: BindGroupLayout,
+	pipeline: ComputePipeline,
+}
+
+impl GpuInverseFft {
+	pub fn new(device: &Device) -> Self {
+		let storage = |binding| BindGroupLayoutEntry {
+			binding,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Storage { read_only: false },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
+		let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+			label: None,
+			entries: &[
+				storage(0),
+				storage(1),
+				BindGroupLayoutEntry {
+					binding: 2,
+					visibility: ShaderStages::COMPUTE,
+					ty: BindingType::Buffer {
+						ty: BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+			],
+		});
+		let module = device.create_shader_module(&ShaderModuleDescriptor {
+			label: None,
+			source: ShaderSource::Wgsl(glsl_to_wgsl(INVERSE_FFT_SHADER).into()),
+		});
+		let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+			label: None,
+			bind_group_layouts: &[&layout],
+			push_constant_ranges: &[],
+		});
+		let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+			label: None,
+			layout: Some(&pipeline_layout),
+			module: &module,
+			entry_point: "main",
+		});
+		Self { layout, pipeline }
+	}
+
+	/// Encodes the unnormalized inverse FFT of `sequences` sequences of `size` values in
+	/// `buffers[0]`. `size` must be a power of two. The buffers are used alternately for
+	/// each stage, and the index of the buffer which has the result is returned.
+	pub fn encode(
+		&self,
+		device: &Device,
+		encoder: &mut CommandEncoder,
+		buffers: [&Buffer; 2],
+		size: u32,
+		sequences: u32,
+	) -> usize {
+		let strides = std::iter::successors(Some(1), |stride| Some(stride * 2))
+			.take_while(|stride| *stride < size)
+			.collect::<Vec<u32>>();
+		let bind_groups: Vec<BindGroup> = strides
+			.iter()
+			.enumerate()
+			.map(|(i, stride)| {
+				let stage = device.create_buffer_init(&util::BufferInitDescriptor {
+					label: None,
+					contents: bytemuck::cast_slice(&[size, *stride, 0, 0]),
+					usage: BufferUsages::UNIFORM,
+				});
+				device.create_bind_group(&BindGroupDescriptor {
+					label: None,
+					layout: &self.layout,
+					entries: &[
+						BindGroupEntry {
+							binding: 0,
+							resource: buffers[i % 2].as_entire_binding(),
+						},
+						BindGroupEntry {
+							binding: 1,
+							resource: buffers[(i + 1) % 2].as_entire_binding(),
+						},
+						BindGroupEntry {
+							binding: 2,
+							resource: stage.as_entire_binding(),
+						},
+					],
+				})
+			})
+			.collect();
+		let mut cpass = encoder.begin_compute_pass(&Default::default());
+		cpass.set_pipeline(&self.pipeline);
+		cpass.insert_debug_marker("inverse FFT");
+		bind_groups.iter().for_each(|bind_group| {
+			cpass.set_bind_group(0, bind_group, &[]);
+			cpass.dispatch(size / 2, sequences, 1);
+		});
+		strides.len() % 2
+	}
+}
+
+#[test]
+fn glsl_to_wgsl_fft_stage_test() {
+	let code = glsl_to_wgsl(INVERSE_FFT_SHADER);
+	println!("{}", code);
+}
@@ -1,5 +1,5 @@
+use crate::sound_source::SourceSpec;
 use hound::*;
-use rustfft::{num_complex::Complex, FftPlanner};
 use std::fs::File;
 use std::io::BufReader;
 use std::path::Path;
@@ -37,95 +37,54 @@ impl Iterator for WrapperSamples {
 
 impl ExactSizeIterator for WrapperSamples {}
 
-pub struct WavTextureMaker {
-	samples: WrapperSamples,
-	buffer: Vec<f32>,
-	fft_buffer: Vec<Complex<f32>>,
-	spec: WavSpec,
-}
-
-impl WavTextureMaker {
-	pub fn try_new<P: AsRef<Path>>(filename: P) -> Result<Self, String> {
-		let wav = WavReader::open(&filename).map_err(|e| format!("{}", e))?;
-		let spec = wav.spec();
-		let samples = match spec.sample_format {
-			SampleFormat::Float => WrapperSamples::F32(wav.into_samples()),
-			SampleFormat::Int => {
-				if spec.bits_per_sample > 16 {
-					WrapperSamples::I32(wav.into_samples(), spec.bits_per_sample)
-				} else {
-					WrapperSamples::I16(wav.into_samples(), spec.bits_per_sample)
-				}
+pub fn open_samples(path: &Path) -> Result<(impl Iterator<Item = f32> + Send, SourceSpec), String> {
+	let wav = WavReader::open(path).map_err(|e| format!("{}", e))?;
+	let spec = wav.spec();
+	let samples = match spec.sample_format {
+		SampleFormat::Float => WrapperSamples::F32(wav.into_samples()),
+		SampleFormat::Int => {
+			if spec.bits_per_sample > 16 {
+				WrapperSamples::I32(wav.into_samples(), spec.bits_per_sample)
+			} else {
+				WrapperSamples::I16(wav.into_samples(), spec.bits_per_sample)
 			}
-		};
-		Ok(Self {
-			samples,
-			spec,
-			buffer: Vec::new(),
-			fft_buffer: Vec::new(),
-		})
-	}
-
-	pub fn spec(&self) -> WavSpec {
-		self.spec
-	}
-
-	pub fn buffer_len(&self) -> usize {
-		self.buffer.len()
-	}
-
-	pub fn reserve(&mut self, len: usize) {
-		let Self {
-			buffer,
-			samples,
-			spec,
-			fft_buffer,
-		} = self;
-		buffer.extend((0..len).map(|_| samples.next().unwrap_or(0.0)));
-		let unit_len = spec.sample_rate as usize / 10;
-		let delta = buffer.len() / spec.channels as usize - fft_buffer.len();
-		if delta > unit_len {
-			let delta = delta - delta % unit_len;
-			let mut planner = FftPlanner::new();
-			let fft = planner.plan_fft_forward(unit_len);
-			let mut new_buffer: Vec<_> = match spec.channels {
-				1 => buffer[fft_buffer.len()..fft_buffer.len() + delta]
-					.iter()
-					.map(|x| Complex { re: *x, im: 0.0 })
-					.collect(),
-				2 => buffer[fft_buffer.len() * 2..(fft_buffer.len() + delta) * 2]
-					.chunks(2)
-					.map(|x| Complex { re: x[0], im: x[1] })
-					.collect(),
-				_ => panic!("unknown channels!"),
-			};
-			fft.process(&mut new_buffer);
-			fft_buffer.extend(new_buffer);
 		}
-	}
+	};
+	let spec = SourceSpec {
+		sample_rate: spec.sample_rate,
+		channels: spec.channels,
+	};
+	Ok((samples, spec))
+}
 
-	pub fn next_buffer(&mut self, len: usize) -> Vec<[f32; 4]> {
-		if self.buffer.len() < len {
-			self.reserve(len - self.buffer.len());
-		}
-		let vec = self.buffer.split_off(len * self.spec.channels as usize);
-		let res0 = self.buffer.clone();
-		self.buffer = vec;
-		let vec = self.fft_buffer.split_off(len);
-		let res1 = self.fft_buffer.clone();
-		self.fft_buffer = vec;
-		match self.spec.channels {
-			1 => res0
-				.iter()
-				.zip(res1)
-				.map(|(x, y)| [*x, 0.0, y.re, y.im])
-				.collect(),
-			2 => res0
-				.chunks(2)
-				.zip(res1)
-				.map(|(x, y)| [x[0], x[1], y.re, y.im])
-				.collect(),
-			_ => panic!("unknown channels!"),
-		}
+#[test]
+fn bit_depth_test() {
+	let dir = std::env::temp_dir().join("sound-shader-bit-depth-test");
+	std::fs::create_dir_all(&dir).unwrap();
+	for bits in [8, 16, 24, 32] {
+		let scale = f32::powi(2.0, bits as i32 - 1);
+		let quantized: Vec<i32> = (0..1000)
+			.map(|i| (f32::sin(i as f32 * 0.1) * (scale - 1.0)) as i32)
+			.collect();
+		let spec = WavSpec {
+			channels: 1,
+			sample_rate: 44100,
+			bits_per_sample: bits,
+			sample_format: SampleFormat::Int,
+		};
+		let path = dir.join(format!("sine{}.wav", bits));
+		let mut writer = WavWriter::create(&path, spec).unwrap();
+		quantized
+			.iter()
+			.for_each(|q| writer.write_sample(*q).unwrap());
+		writer.finalize().unwrap();
+		let (samples, spec) = open_samples(&path).unwrap();
+		assert_eq!(spec.channels, 1);
+		let samples: Vec<f32> = samples.collect();
+		assert_eq!(samples.len(), quantized.len());
+		samples.iter().zip(&quantized).for_each(|(x, q)| {
+			let answer = *q as f32 / scale;
+			assert!(f32::abs(x - answer) < 1.0e-6, "{} {} {}", bits, x, answer);
+		});
 	}
 }
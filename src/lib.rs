@@ -1,17 +1,57 @@
 use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc::Sender,
+    Arc, Mutex,
+};
 use std::time::Duration;
 
+mod convolver;
+use convolver::Convolver;
 mod cpal_wrapper;
-use cpal_wrapper::StreamFactory;
+mod envelope;
+mod feedback;
+mod gpu_fft;
 mod wgpu_wrapper;
-use wgpu_wrapper::GPUDirector;
+use wgpu_wrapper::{GPUDirector, ShaderFeatures, ShaderMode, SpectralBlock, StorageBinding};
+mod encoder;
 mod hound_wrapper;
-use hound_wrapper::WavTextureMaker;
+pub use encoder::{AudioFileWriter, Dither, OutputFormat};
+mod recorder;
+pub use recorder::Recorder;
+mod sink;
+pub use sink::{AudioSink, DeviceSink, FileSink, NullSink, RawFormat, StdoutSink};
+
+/// Sample rate of `run` when no sink requires a specific rate
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+mod options;
+mod oversampling;
+pub use options::{
+    CompressorOptions, ConvolutionOptions, EndBehaviour, EqBand, Filter, Interpolation,
+    LimiterOptions, PostChainOptions, SpectralInput, SpectralOptions, StftOptions, StorageOptions,
+    Window, MAX_IMPULSE_RESPONSE_SECONDS, MAX_RANDOM_ACCESS_SECONDS,
+};
+use oversampling::{Decimator, DECIMATOR_DELAY};
+mod pitch;
+mod playback;
+mod post_chain;
+use post_chain::PostChain;
+mod resampler;
+use resampler::Resampler;
+mod sample_library;
+pub use sample_library::SampleLibrary;
+mod sound_source;
+mod spectrum;
+mod stft;
+use stft::{Istft, Stft};
+mod symphonia_wrapper;
+pub use sound_source::{SoundSource, SourceBuffer, SourceSpec, TextureMaker};
 
 /// Options for `cpal` audio device.
+#[derive(Default)]
 pub enum AudioDevice {
     /// Loads the default audio device internally
+    #[default]
     Default,
     /// Set the audio device manually
     Custum {
@@ -22,13 +62,11 @@ pub enum AudioDevice {
     },
 }
 
-impl Default for AudioDevice {
-    fn default() -> Self { Self::Default }
-}
-
 /// Options for `wgpu` GPU device.
+#[derive(Default)]
 pub enum GpuDevice {
     /// Loads the default GPU device internally
+    #[default]
     Default,
     /// Set the GPU device manually
     Custum {
@@ -39,8 +77,26 @@ pub enum GpuDevice {
     },
 }
 
-impl Default for GpuDevice {
-    fn default() -> Self { Self::Default }
+impl GpuDevice {
+    /// Loads the default device if needed.
+    fn into_gpu(self) -> Gpu {
+        match self {
+            Self::Default => {
+                let (device, queue) = wgpu_wrapper::init_device();
+                Gpu {
+                    device: Arc::new(device),
+                    queue: Arc::new(queue),
+                }
+            }
+            Self::Custum { device, queue } => Gpu { device, queue },
+        }
+    }
+}
+
+/// GPU device shared by the shader and the convolutions
+struct Gpu {
+    device: Arc<wgpu::Device>,
+    queue: Arc<wgpu::Queue>,
 }
 
 /// Configuation for shader stream
@@ -53,8 +109,40 @@ pub struct ShaderStreamDescriptor<'a, P: AsRef<Path> = &'static str> {
     pub shader_source: &'a str,
     /// File names of sound storages
     pub sound_storages: &'a [P],
+    /// Additional sound storages, numbered after `sound_storages`.
+    pub sound_sources: Vec<Arc<Mutex<dyn SoundSource>>>,
+    /// Options for `sound_storages` followed by `sound_sources`. Missing entries are default.
+    pub storage_options: &'a [StorageOptions],
+    /// Files or directories of one-shots for `sampleFetch`. See `SampleLibrary::load`.
+    pub sample_library: &'a [P],
     /// Buffer for recording result
     pub record_buffer: Option<Arc<Mutex<Vec<f32>>>>,
+    /// Channel which receives played samples, e.g. `Recorder::sender`.
+    pub record_sender: Option<Sender<Vec<f32>>>,
+    /// Sample rate of rendering, `iSampleRate`. If the audio device requires another rate,
+    /// the output is resampled to the rate of the device, so that the shader sounds the same
+    /// on every device. `None` uses the rate of the audio device, or `DEFAULT_SAMPLE_RATE`
+    /// without devices.
+    pub sample_rate: Option<u32>,
+    /// FFT size of `vec4 mainSpectrum(uint bin, uint frame)`, a power of two. The spectra are
+    /// converted to samples by the inverse FFT on GPU, fetched by `inverseFFTFetch` in
+    /// `mainSound`. `None` disables `mainSpectrum`.
+    pub inverse_fft: Option<u32>,
+    /// Analyses the previous block of the output and feeds it back to the shader by
+    /// `iFeedbackRMS` and `feedbackSpectrum`.
+    pub feedback: bool,
+    /// Convolution applied to the output of the shader, e.g. a reverb
+    pub convolution: Option<ConvolutionOptions>,
+    /// Master chain applied to the output after the convolution, e.g. a limiter which keeps
+    /// the output from clipping
+    pub post_chain: Option<PostChainOptions>,
+    /// Factor of oversampling, 2, 4 or 8. The shader is rendered at the multiplied sample rate,
+    /// and decimated by a low-pass filter, which reduces aliasing. The delay of the filter
+    /// is compensated. `None` disables oversampling.
+    pub oversampling: Option<u32>,
+    /// Plays the inverse STFT of `mainSpectral` of the shader run on the STFT of the input,
+    /// instead of `mainSound`. See `ShaderSpectral`. `None` disables `mainSpectral`.
+    pub spectral: Option<SpectralOptions>,
 }
 
 impl<'a> Default for ShaderStreamDescriptor<'a> {
@@ -64,11 +152,299 @@ impl<'a> Default for ShaderStreamDescriptor<'a> {
             gpu_device: GpuDevice::Default,
             shader_source: "",
             sound_storages: &[],
+            sound_sources: Vec::new(),
+            storage_options: &[],
+            sample_library: &[],
             record_buffer: None,
+            record_sender: None,
+            sample_rate: None,
+            inverse_fft: None,
+            feedback: false,
+            convolution: None,
+            post_chain: None,
+            oversampling: None,
+            spectral: None,
+        }
+    }
+}
+
+fn load_sound_storages<P: AsRef<Path>>(
+    sound_storages: &[P],
+    sound_sources: Vec<Arc<Mutex<dyn SoundSource>>>,
+    storage_options: &[StorageOptions],
+    gpu: &Gpu,
+) -> Result<Vec<Arc<Mutex<dyn SoundSource>>>, String> {
+    sound_storages
+        .iter()
+        .enumerate()
+        .map(|(i, path)| {
+            let options = storage_options.get(i).cloned().unwrap_or_default();
+            let mut maker = TextureMaker::try_new(path)
+                .map_err(|e| format!("{}: {}", path.as_ref().display(), e))?
+                .with_playback(&options);
+            if let Some(convolution) = options.convolution.as_ref() {
+                maker = maker.with_convolution(convolution, &gpu.device, &gpu.queue)?;
+            }
+            Ok(Arc::new(Mutex::new(maker)) as Arc<Mutex<dyn SoundSource>>)
+        })
+        .chain(sound_sources.into_iter().map(Ok))
+        .collect()
+}
+
+fn load_sample_library<P: AsRef<Path>>(paths: &[P]) -> Result<Option<SampleLibrary>, String> {
+    if paths.is_empty() {
+        Ok(None)
+    } else {
+        SampleLibrary::load(paths).map(Some)
+    }
+}
+
+/// Returns the sound storages except random-access ones, which are loaded at once.
+fn streamed_storages(
+    sound_storages: &[Arc<Mutex<dyn SoundSource>>],
+    storage_options: &[StorageOptions],
+) -> Vec<Arc<Mutex<dyn SoundSource>>> {
+    sound_storages
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !storage_options.get(*i).is_some_and(|o| o.random_access))
+        .map(|(_, storage)| Arc::clone(storage))
+        .collect()
+}
+
+/// Shader rendered by `Renderer`
+enum RenderedShader {
+    Sound(Box<GPUDirector>),
+    Spectral(Box<ShaderSpectral>),
+}
+
+impl RenderedShader {
+    fn render(&mut self, sample_rate: u32, frames: u32) -> Vec<f32> {
+        match self {
+            Self::Sound(director) => director.render(sample_rate, frames * 2),
+            Self::Spectral(spectral) => spectral.render(sample_rate, frames),
+        }
+    }
+}
+
+/// Renders blocks of the shader, decimated from the oversampled rate, resampled to the output
+/// rate, convolved and processed by the master chain if they are enabled.
+struct Renderer {
+    shader: RenderedShader,
+    /// Sample rate of rendering before oversampling
+    sample_rate: u32,
+    oversampling: u32,
+    decimator: Option<Decimator>,
+    /// Frames of the decimated output still to be dropped to compensate the delay of `decimator`
+    latency: usize,
+    /// Interleaved samples rendered ahead of the blocks requested, at the rendering sample rate
+    rendered: Vec<f32>,
+    resampler: Option<Resampler>,
+    convolver: Option<Convolver>,
+    post_chain: Option<PostChain>,
+}
+
+impl Renderer {
+    fn new(
+        shader: RenderedShader,
+        sample_rate: u32,
+        output_sample_rate: u32,
+        oversampling: Option<u32>,
+        convolution: Option<&ConvolutionOptions>,
+        post_chain: Option<&PostChainOptions>,
+        gpu: &Gpu,
+    ) -> Result<Self, String> {
+        let oversampling = oversampling.unwrap_or(1);
+        if ![1, 2, 4, 8].contains(&oversampling) {
+            return Err(format!(
+                "oversampling factor must be 2, 4 or 8: {}",
+                oversampling
+            ));
+        }
+        let decimator = if oversampling > 1 {
+            Some(Decimator::new(oversampling as usize))
+        } else {
+            None
+        };
+        let resampler = if sample_rate != output_sample_rate {
+            Some(Resampler::new(sample_rate, output_sample_rate))
+        } else {
+            None
+        };
+        let convolver = convolution
+            .map(|convolution| {
+                Convolver::load(convolution, output_sample_rate, 2, &gpu.device, &gpu.queue)
+            })
+            .transpose()?;
+        let post_chain = post_chain.map(|options| PostChain::new(options, output_sample_rate));
+        Ok(Self {
+            shader,
+            sample_rate,
+            oversampling,
+            latency: if decimator.is_some() {
+                DECIMATOR_DELAY
+            } else {
+                0
+            },
+            rendered: Vec::new(),
+            decimator,
+            resampler,
+            convolver,
+            post_chain,
+        })
+    }
+
+    /// Renders `frames` frames at the rendering sample rate.
+    fn render_shader(&mut self, frames: u32) -> Vec<f32> {
+        if self.decimator.is_none() && self.resampler.is_none() {
+            return self.shader.render(self.sample_rate, frames);
+        }
+        // the blocks are rendered by whole seconds, as `soundTexelFetch` assumes, even if the
+        // resampler requires other lengths. The delay of the decimator is dropped from the start,
+        // and the frames rendered ahead are kept for the next blocks.
+        let frames = frames as usize;
+        while self.rendered.len() < frames * 2 {
+            let sample_rate = self.sample_rate * self.oversampling;
+            let mut block = self.shader.render(sample_rate, sample_rate);
+            if let Some(decimator) = self.decimator.as_mut() {
+                block = decimator.process(&block);
+            }
+            let drop = usize::min(self.latency, block.len() / 2);
+            self.rendered.extend_from_slice(&block[drop * 2..]);
+            self.latency -= drop;
+        }
+        let rest = self.rendered.split_off(frames * 2);
+        std::mem::replace(&mut self.rendered, rest)
+    }
+
+    /// Renders the next `buffer_length` interleaved samples at the output sample rate.
+    fn render(&mut self, buffer_length: u32) -> Vec<f32> {
+        let frames = buffer_length as usize / 2;
+        let input_frames = self
+            .resampler
+            .as_ref()
+            .map_or(frames, |resampler| resampler.input_frames(frames));
+        let mut block = self.render_shader(input_frames as u32);
+        if let Some(resampler) = self.resampler.as_mut() {
+            block = resampler.process(&block, frames);
+        }
+        if let Some(convolver) = self.convolver.as_mut() {
+            block = convolver.process(&block);
+        }
+        if let Some(post_chain) = self.post_chain.as_mut() {
+            post_chain.process(&mut block);
         }
+        block
     }
 }
 
+fn reserve_storages(
+    sound_storages: &[Arc<Mutex<dyn SoundSource>>],
+    frames: usize,
+    sample_rate: u32,
+) {
+    sound_storages.iter().for_each(|storage| {
+        let mut storage = storage.lock().unwrap();
+        let spec = storage.spec();
+        let unit_len = spec.sample_rate as usize / 5;
+        let frames = frames * spec.sample_rate as usize / sample_rate as usize;
+        let len = (frames + unit_len) * spec.channels as usize;
+        let current_len = storage.buffer_len();
+        if current_len < len {
+            storage.reserve(len - current_len);
+        }
+    });
+}
+
+fn create_director(
+    gpu: &Gpu,
+    shader_source: &str,
+    sound_storages: Vec<Arc<Mutex<dyn SoundSource>>>,
+    storage_options: &[StorageOptions],
+    sample_library: Option<&SampleLibrary>,
+    mode: ShaderMode,
+    features: ShaderFeatures,
+) -> Result<GPUDirector, String> {
+    if let Some(size) = features.inverse_fft {
+        if size < 2 || !size.is_power_of_two() {
+            return Err(format!("inverse FFT size must be a power of two: {}", size));
+        }
+    }
+    if let ShaderMode::Spectral(input) = mode {
+        if features.inverse_fft.is_some() {
+            return Err("inverse FFT is not supported by spectral shaders".to_string());
+        }
+        if features.feedback {
+            return Err("feedback is not supported by spectral shaders".to_string());
+        }
+        if let Some(SpectralInput::Resource(n)) = input {
+            if n >= sound_storages.len() {
+                return Err(format!(
+                    "resource of the spectral input is not found: {}",
+                    n
+                ));
+            }
+        }
+    }
+    let sound_storages: Vec<StorageBinding> = sound_storages
+        .into_iter()
+        .enumerate()
+        .map(|(i, source)| {
+            let options = storage_options.get(i).cloned().unwrap_or_default();
+            StorageBinding::new(source, options)
+        })
+        .collect();
+    wgpu_wrapper::check_resource_names(shader_source, &sound_storages)?;
+    Ok(GPUDirector::new(
+        Arc::clone(&gpu.device),
+        Arc::clone(&gpu.queue),
+        shader_source,
+        sound_storages,
+        sample_library,
+        mode,
+        features,
+    ))
+}
+
+/// Creates the director of `mainSound`, or of `mainSpectral` if `spectral` is given.
+fn create_shader(
+    gpu: &Gpu,
+    shader_source: &str,
+    sound_storages: Vec<Arc<Mutex<dyn SoundSource>>>,
+    storage_options: &[StorageOptions],
+    sample_library: Option<&SampleLibrary>,
+    features: ShaderFeatures,
+    spectral: Option<&SpectralOptions>,
+) -> Result<RenderedShader, String> {
+    let spectral = match spectral {
+        Some(spectral) => spectral,
+        None => {
+            return create_director(
+                gpu,
+                shader_source,
+                sound_storages,
+                storage_options,
+                sample_library,
+                ShaderMode::Sound,
+                features,
+            )
+            .map(|director| RenderedShader::Sound(Box::new(director)))
+        }
+    };
+    let streamed = streamed_storages(&sound_storages, storage_options);
+    let director = create_director(
+        gpu,
+        shader_source,
+        sound_storages,
+        storage_options,
+        sample_library,
+        ShaderMode::Spectral(Some(spectral.input)),
+        features,
+    )?;
+    let spectral = ShaderSpectral::from_director(director, streamed, &spectral.stft);
+    Ok(RenderedShader::Spectral(Box::new(spectral)))
+}
+
 /// Creates output audio stream
 pub fn stream<P: AsRef<Path>>(
     desc: ShaderStreamDescriptor<P>,
@@ -78,43 +454,71 @@ pub fn stream<P: AsRef<Path>>(
         gpu_device,
         shader_source,
         sound_storages,
-        mut record_buffer,
+        sound_sources,
+        storage_options,
+        sample_library,
+        record_buffer,
+        record_sender,
+        sample_rate: desc_sample_rate,
+        inverse_fft,
+        feedback,
+        convolution,
+        post_chain,
+        oversampling,
+        spectral,
     } = desc;
-    let sf = match audio_device {
-        AudioDevice::Default => StreamFactory::default_factory().unwrap(),
-        AudioDevice::Custum { device, config } => StreamFactory::new(device, config),
-    };
-    let config = sf.config();
-    let sound_storages = sound_storages
-        .iter()
-        .map(|path| {
-            let mut maker = WavTextureMaker::try_new(path).unwrap();
-            let spec = maker.spec();
-            maker.reserve(spec.sample_rate as usize * 3);
-            Arc::new(Mutex::new(maker))
-        })
-        .collect::<Vec<_>>();
-    let sound_storages0 = sound_storages.clone();
-    let mut director = match gpu_device {
-        GpuDevice::Default => GPUDirector::from_default_device(shader_source, sound_storages),
-        GpuDevice::Custum { device, queue } => {
-            GPUDirector::new(device, queue, shader_source, sound_storages)
-        }
-    };
+    let sink = DeviceSink::with_records(audio_device, record_buffer, record_sender).unwrap();
+    let (stream, config, buffer) = sink.into_parts();
+    let sample_rate = config.sample_rate.0;
+    let render_sample_rate = desc_sample_rate.unwrap_or(sample_rate);
+    let gpu = gpu_device.into_gpu();
+    let sound_storages =
+        load_sound_storages(sound_storages, sound_sources, storage_options, &gpu).unwrap();
+    let sample_library = load_sample_library(sample_library).unwrap();
+    let sound_storages0 = streamed_storages(&sound_storages, storage_options);
+    sound_storages0.iter().for_each(|storage| {
+        let mut storage = storage.lock().unwrap();
+        let spec = storage.spec();
+        storage.reserve(spec.sample_rate as usize * 3);
+    });
+    let shader = create_shader(
+        &gpu,
+        shader_source,
+        sound_storages,
+        storage_options,
+        sample_library.as_ref(),
+        ShaderFeatures {
+            inverse_fft,
+            feedback,
+        },
+        spectral.as_ref(),
+    )
+    .unwrap();
 
-    let sample_rate = config.sample_rate.0 as u32;
-    let buffer0 = Arc::new(Mutex::new(director.render(sample_rate, sample_rate * 2)));
-    let buffer1 = Arc::clone(&buffer0);
+    let mut renderer = Renderer::new(
+        shader,
+        render_sample_rate,
+        sample_rate,
+        oversampling,
+        convolution.as_ref(),
+        post_chain.as_ref(),
+        &gpu,
+    )
+    .unwrap();
+    buffer
+        .lock()
+        .unwrap()
+        .extend(renderer.render(sample_rate * 2));
 
     if !sound_storages0.is_empty() {
         std::thread::spawn(move || loop {
-            sound_storages0.iter().for_each(|wav| {
-                let mut wav = wav.lock().unwrap();
-                let spec = wav.spec();
+            sound_storages0.iter().for_each(|storage| {
+                let mut storage = storage.lock().unwrap();
+                let spec = storage.spec();
                 let unit_len = spec.sample_rate as usize;
-                let current_len = wav.buffer_len();
+                let current_len = storage.buffer_len();
                 if current_len < unit_len * 2 {
-                    wav.reserve(unit_len * 3 - current_len);
+                    storage.reserve(unit_len * 3 - current_len);
                 }
             });
             std::thread::sleep(Duration::from_millis(100));
@@ -122,43 +526,14 @@ pub fn stream<P: AsRef<Path>>(
     }
 
     std::thread::spawn(move || loop {
-        let len = buffer0.lock().unwrap().len() as u32;
+        let len = buffer.lock().unwrap().len() as u32;
         if len < sample_rate {
-            let vec = director.render(sample_rate, sample_rate * 2);
-            buffer0.lock().unwrap().extend(vec);
+            let vec = renderer.render(sample_rate * 2);
+            buffer.lock().unwrap().extend(vec);
         }
         std::thread::sleep(Duration::from_millis(200));
     });
 
-    let record = record_buffer.take();
-    let stream = sf
-        .create_stream(move |len| match buffer1.lock() {
-            Err(e) => {
-                eprintln!("{}", e);
-                vec![0.0; len]
-            }
-            Ok(mut buffer) => {
-                if buffer.len() < len {
-                    eprintln!(
-                        "buffer length is not enough.\nbuffer length: {}\nrequired: {}",
-                        buffer.len(),
-                        len
-                    );
-                    buffer.resize(len, 0.0);
-                }
-                let latter = buffer.split_off(len);
-                let front = buffer.clone();
-                if let Some(record) = record.as_ref() {
-                    match record.try_lock() {
-                        Ok(mut record) => record.extend(&front),
-                        Err(_) => eprintln!("record buffer is locked"),
-                    }
-                }
-                *buffer = latter;
-                front
-            }
-        })
-        .unwrap();
     (stream, config)
 }
 
@@ -174,7 +549,9 @@ pub fn play<P: AsRef<Path>>(
     Ok(config)
 }
 
-/// Returns buffer for play the shader. `audio_device` and `record_buffer` are ignored.
+/// Returns buffer for play the shader at `sample_rate`. The shader is rendered at `sample_rate`
+/// of `desc` if it is given, and resampled to `sample_rate`.
+/// `audio_device`, `record_buffer` and `record_sender` are ignored.
 pub fn write_buffer<P: AsRef<Path>>(
     desc: ShaderStreamDescriptor<P>,
     sample_rate: u32,
@@ -184,27 +561,380 @@ pub fn write_buffer<P: AsRef<Path>>(
         gpu_device,
         shader_source,
         sound_storages,
+        sound_sources,
+        storage_options,
+        sample_library,
+        sample_rate: render_sample_rate,
+        inverse_fft,
+        feedback,
+        convolution,
+        post_chain,
+        oversampling,
+        spectral,
         ..
     } = desc;
-    let sound_storages = sound_storages
+    let gpu = gpu_device.into_gpu();
+    let sound_storages =
+        load_sound_storages(sound_storages, sound_sources, storage_options, &gpu).unwrap();
+    let sample_library = load_sample_library(sample_library).unwrap();
+    streamed_storages(&sound_storages, storage_options)
         .iter()
-        .map(|path| {
-            let mut wav = WavTextureMaker::try_new(path).unwrap();
-            let spec = wav.spec();
+        .for_each(|storage| {
+            let mut storage = storage.lock().unwrap();
+            let spec = storage.spec();
             let len = spec.sample_rate as usize
                 * spec.channels as usize
                 * (duration.as_secs() as usize + 1);
-            wav.reserve(len);
-            Arc::new(Mutex::new(wav))
-        })
-        .collect();
-    let mut director = match gpu_device {
-        GpuDevice::Default => GPUDirector::from_default_device(shader_source, sound_storages),
-        GpuDevice::Custum { device, queue } => {
-            GPUDirector::new(device, queue, shader_source, sound_storages)
-        }
-    };
+            storage.reserve(len);
+        });
+    let shader = create_shader(
+        &gpu,
+        shader_source,
+        sound_storages,
+        storage_options,
+        sample_library.as_ref(),
+        ShaderFeatures {
+            inverse_fft,
+            feedback,
+        },
+        spectral.as_ref(),
+    )
+    .unwrap();
+    let mut renderer = Renderer::new(
+        shader,
+        render_sample_rate.unwrap_or(sample_rate),
+        sample_rate,
+        oversampling,
+        convolution.as_ref(),
+        post_chain.as_ref(),
+        &gpu,
+    )
+    .unwrap();
     let time = duration.as_secs_f64();
     let buffer_length = (sample_rate as f64 * time) as u32 * 2;
-    director.render(sample_rate, buffer_length)
+    renderer.render(buffer_length)
+}
+
+/// Renders the shader into all `sinks` until `duration` of audio is rendered, or `running` is
+/// cleared. `duration: None` renders endlessly.
+///
+/// The shader is rendered at `sample_rate` of `desc`, the one required by the first sink which
+/// requires it, or `DEFAULT_SAMPLE_RATE`. If the sink requires another rate, the output is
+/// resampled to it. `audio_device`, `record_buffer` and `record_sender` are ignored;
+/// use `DeviceSink` and `FileSink` instead.
+pub fn run<P: AsRef<Path>>(
+    desc: ShaderStreamDescriptor<P>,
+    sinks: &mut [Box<dyn AudioSink>],
+    duration: Option<Duration>,
+    running: &AtomicBool,
+) -> Result<(), String> {
+    let ShaderStreamDescriptor {
+        gpu_device,
+        shader_source,
+        sound_storages,
+        sound_sources,
+        storage_options,
+        sample_library,
+        sample_rate,
+        inverse_fft,
+        feedback,
+        convolution,
+        post_chain,
+        oversampling,
+        spectral,
+        ..
+    } = desc;
+    let device_sample_rate = sinks.iter().find_map(|sink| sink.sample_rate());
+    let render_sample_rate = sample_rate
+        .or(device_sample_rate)
+        .unwrap_or(DEFAULT_SAMPLE_RATE);
+    let sample_rate = device_sample_rate.unwrap_or(render_sample_rate);
+    let gpu = gpu_device.into_gpu();
+    let sound_storages = load_sound_storages(sound_storages, sound_sources, storage_options, &gpu)?;
+    let sample_library = load_sample_library(sample_library)?;
+    let streamed = streamed_storages(&sound_storages, storage_options);
+    let shader = create_shader(
+        &gpu,
+        shader_source,
+        sound_storages,
+        storage_options,
+        sample_library.as_ref(),
+        ShaderFeatures {
+            inverse_fft,
+            feedback,
+        },
+        spectral.as_ref(),
+    )?;
+    let mut renderer = Renderer::new(
+        shader,
+        render_sample_rate,
+        sample_rate,
+        oversampling,
+        convolution.as_ref(),
+        post_chain.as_ref(),
+        &gpu,
+    )?;
+    sinks
+        .iter_mut()
+        .try_for_each(|sink| sink.start(sample_rate))?;
+    // `soundTexelFetch` assumes that each block starts at a whole second.
+    let mut remaining =
+        duration.map(|duration| (duration.as_secs_f64() * sample_rate as f64) as u64);
+    while running.load(Ordering::SeqCst) && remaining != Some(0) {
+        let frames = match remaining.as_mut() {
+            Some(remaining) => {
+                let frames = u64::min(*remaining, sample_rate as u64);
+                *remaining -= frames;
+                frames as u32
+            }
+            None => sample_rate,
+        };
+        reserve_storages(&streamed, frames as usize, sample_rate);
+        let block = renderer.render(frames * 2);
+        sinks.iter_mut().try_for_each(|sink| sink.write(&block))?;
+    }
+    sinks.iter_mut().try_for_each(|sink| sink.finish())
+}
+
+/// Configuation for shader effect
+pub struct ShaderEffectDescriptor<'a, P: AsRef<Path> = &'static str> {
+    /// Options for `wgpu` GPU device.
+    pub gpu_device: GpuDevice,
+    /// Effect shader code, which implements `mainEffect` instead of `mainSound`.
+    pub shader_source: &'a str,
+    /// File names of sound storages
+    pub sound_storages: &'a [P],
+    /// Additional sound storages, numbered after `sound_storages`.
+    pub sound_sources: Vec<Arc<Mutex<dyn SoundSource>>>,
+    /// Options for `sound_storages` followed by `sound_sources`. Missing entries are default.
+    pub storage_options: &'a [StorageOptions],
+    /// Files or directories of one-shots for `sampleFetch`. See `SampleLibrary::load`.
+    pub sample_library: &'a [P],
+    /// Analyses the previous block of the output and feeds it back to the shader by
+    /// `iFeedbackRMS` and `feedbackSpectrum`.
+    pub feedback: bool,
+}
+
+impl<'a> Default for ShaderEffectDescriptor<'a> {
+    fn default() -> Self {
+        Self {
+            gpu_device: GpuDevice::Default,
+            shader_source: "",
+            sound_storages: &[],
+            sound_sources: Vec::new(),
+            storage_options: &[],
+            sample_library: &[],
+            feedback: false,
+        }
+    }
+}
+
+/// Audio processing node driven by a shader.
+///
+/// The shader implements `vec2 mainEffect(vec2 input, uint samp, float time)`.
+/// `input` is the frame `samp` of the current block; the whole block can be read
+/// from `iInput[0..iBlockLength]`.
+pub struct ShaderEffect {
+    director: GPUDirector,
+    sound_storages: Vec<Arc<Mutex<dyn SoundSource>>>,
+}
+
+impl ShaderEffect {
+    /// Compiles the effect shader and loads the sound storages.
+    pub fn new<P: AsRef<Path>>(desc: ShaderEffectDescriptor<P>) -> Self {
+        let ShaderEffectDescriptor {
+            gpu_device,
+            shader_source,
+            sound_storages,
+            sound_sources,
+            storage_options,
+            sample_library,
+            feedback,
+        } = desc;
+        let gpu = gpu_device.into_gpu();
+        let sound_storages =
+            load_sound_storages(sound_storages, sound_sources, storage_options, &gpu).unwrap();
+        let sample_library = load_sample_library(sample_library).unwrap();
+        let streamed = streamed_storages(&sound_storages, storage_options);
+        let director = create_director(
+            &gpu,
+            shader_source,
+            sound_storages,
+            storage_options,
+            sample_library.as_ref(),
+            ShaderMode::Effect,
+            ShaderFeatures {
+                inverse_fft: None,
+                feedback,
+            },
+        )
+        .unwrap();
+        Self {
+            director,
+            sound_storages: streamed,
+        }
+    }
+
+    /// Processes one block of interleaved stereo frames and returns the output block
+    /// of the same length. Blocks are treated as consecutive in time.
+    pub fn process(&mut self, sample_rate: u32, input: &[f32]) -> Vec<f32> {
+        let frames = input.len() / 2;
+        if frames == 0 {
+            return Vec::new();
+        }
+        reserve_storages(&self.sound_storages, frames, sample_rate);
+        self.director.process(sample_rate, &input[..frames * 2])
+    }
+}
+
+/// Configuation for shader spectral processing
+pub struct ShaderSpectralDescriptor<'a, P: AsRef<Path> = &'static str> {
+    /// Options for `wgpu` GPU device.
+    pub gpu_device: GpuDevice,
+    /// Spectral shader code, which implements `mainSpectral` instead of `mainSound`.
+    pub shader_source: &'a str,
+    /// FFT size, hop and window of the STFT around the shader
+    pub stft: StftOptions,
+    /// Input rendered by `ShaderSpectral::render`. `None` takes blocks by `ShaderSpectral::process`.
+    pub input: Option<SpectralInput>,
+    /// File names of sound storages
+    pub sound_storages: &'a [P],
+    /// Additional sound storages, numbered after `sound_storages`.
+    pub sound_sources: Vec<Arc<Mutex<dyn SoundSource>>>,
+    /// Options for `sound_storages` followed by `sound_sources`. Missing entries are default.
+    pub storage_options: &'a [StorageOptions],
+    /// Files or directories of one-shots for `sampleFetch`. See `SampleLibrary::load`.
+    pub sample_library: &'a [P],
+}
+
+impl<'a> Default for ShaderSpectralDescriptor<'a> {
+    fn default() -> Self {
+        Self {
+            gpu_device: GpuDevice::Default,
+            shader_source: "",
+            stft: StftOptions::default(),
+            input: None,
+            sound_storages: &[],
+            sound_sources: Vec::new(),
+            storage_options: &[],
+            sample_library: &[],
+        }
+    }
+}
+
+/// Audio processing node which runs a shader on the STFT of the input.
+///
+/// The shader implements `vec2 mainSpectral(uint bin, uint frame, vec2 spectrum)`, called for
+/// the bins `0..=iSpectralSize / 2` of the left and right channels of each STFT frame.
+/// `spectrum` is the complex bin `bin` of the frame `frame`, which is the windowed FFT of the
+/// `iSpectralSize` frames before `frame * iSpectralHop`, and the returned bin is overlap-added
+/// back to audio by the inverse STFT. The output is normalised by the overlapping windows, so that
+/// unmodified bins reconstruct the input for any size, hop and window whose frames cover the
+/// stream. The frames of the current block can be read from `iSpectralInput`, `iSpectralFrames`
+/// frames from `iSpectralBaseFrame`, each of which has `iSpectralSize / 2 + 1` bins as
+/// `vec4(left, right)`.
+///
+/// The output is delayed by the FFT size. Blocks of the input are given to `process`,
+/// or rendered by `render` from `input` of the descriptor: the dry output of `mainSound` of the
+/// same shader, or a resource read by `soundTexture{n}`.
+pub struct ShaderSpectral {
+    director: GPUDirector,
+    sound_storages: Vec<Arc<Mutex<dyn SoundSource>>>,
+    stft: Stft,
+    istft: Istft,
+    /// The next STFT frame to be processed
+    next_frame: usize,
+}
+
+impl ShaderSpectral {
+    /// Compiles the spectral shader and loads the sound storages.
+    pub fn new<P: AsRef<Path>>(desc: ShaderSpectralDescriptor<P>) -> Self {
+        let ShaderSpectralDescriptor {
+            gpu_device,
+            shader_source,
+            stft,
+            input,
+            sound_storages,
+            sound_sources,
+            storage_options,
+            sample_library,
+        } = desc;
+        let gpu = gpu_device.into_gpu();
+        let sound_storages =
+            load_sound_storages(sound_storages, sound_sources, storage_options, &gpu).unwrap();
+        let sample_library = load_sample_library(sample_library).unwrap();
+        let streamed = streamed_storages(&sound_storages, storage_options);
+        let director = create_director(
+            &gpu,
+            shader_source,
+            sound_storages,
+            storage_options,
+            sample_library.as_ref(),
+            ShaderMode::Spectral(input),
+            ShaderFeatures::default(),
+        )
+        .unwrap();
+        Self::from_director(director, streamed, &stft)
+    }
+
+    fn from_director(
+        director: GPUDirector,
+        sound_storages: Vec<Arc<Mutex<dyn SoundSource>>>,
+        stft: &StftOptions,
+    ) -> Self {
+        Self {
+            director,
+            sound_storages,
+            stft: Stft::new(stft, 2),
+            istft: Istft::new(stft),
+            next_frame: 0,
+        }
+    }
+
+    /// Processes one block of interleaved stereo frames and returns the output block
+    /// of the same length. Blocks are treated as consecutive in time.
+    pub fn process(&mut self, sample_rate: u32, input: &[f32]) -> Vec<f32> {
+        let frames = input.len() / 2;
+        if frames == 0 {
+            return Vec::new();
+        }
+        reserve_storages(&self.sound_storages, frames, sample_rate);
+        self.process_block(sample_rate, &input[..frames * 2])
+    }
+
+    /// Renders the next `frames` frames of the input given by `input` of the descriptor,
+    /// and returns the output block of interleaved stereo frames.
+    pub fn render(&mut self, sample_rate: u32, frames: u32) -> Vec<f32> {
+        if frames == 0 {
+            return Vec::new();
+        }
+        reserve_storages(&self.sound_storages, frames as usize, sample_rate);
+        let input = self.director.render_dry(sample_rate, frames);
+        self.process_block(sample_rate, &input)
+    }
+
+    fn process_block(&mut self, sample_rate: u32, input: &[f32]) -> Vec<f32> {
+        let frames = input.len() / 2;
+        let bins = self.stft.bins();
+        let (first, spectra) = self.stft.process(input);
+        // the last frame of the previous block is returned again.
+        let base_frame = usize::max(first, self.next_frame);
+        let spectra = &spectra[(base_frame - first) * bins..];
+        let block = SpectralBlock {
+            size: self.stft.size() as u32,
+            hop: self.stft.hop() as u32,
+            base_frame: base_frame as u32,
+            frames: (spectra.len() / bins) as u32,
+            bins: spectra,
+        };
+        let output = self
+            .director
+            .process_spectral(sample_rate, frames as u32, &block);
+        let output: &[[f32; 4]] = bytemuck::cast_slice(&output);
+        output.chunks(bins).enumerate().for_each(|(i, bins)| {
+            self.istft.add(base_frame + i, bins);
+        });
+        self.next_frame = base_frame + block.frames as usize;
+        self.istft.pop(frames)
+    }
 }
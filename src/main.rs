@@ -1,15 +1,57 @@
-use sound_shader::ShaderStreamDescriptor;
+use sound_shader::{
+    AudioSink, ConvolutionOptions, DeviceSink, FileSink, OutputFormat, PostChainOptions, RawFormat,
+    ShaderStreamDescriptor, SpectralInput, SpectralOptions, StdoutSink, StorageOptions,
+};
 use std::path::Path;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
+    Arc,
 };
+use std::time::Duration;
+
+/// Audio resource given by a file name, or with storage options
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(untagged)]
+pub enum ResourceConfig<P> {
+    Path(P),
+    Detailed {
+        path: P,
+        #[serde(flatten)]
+        options: StorageOptions,
+    },
+}
+
+impl<P> ResourceConfig<P> {
+    fn path(&self) -> &P {
+        match self {
+            Self::Path(path) => path,
+            Self::Detailed { path, .. } => path,
+        }
+    }
+    fn options(&self) -> StorageOptions {
+        match self {
+            Self::Path(_) => StorageOptions::default(),
+            Self::Detailed { options, .. } => options.clone(),
+        }
+    }
+}
 
 #[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct PlayConfig<P: AsRef<Path> = &'static str> {
     shader_source: P,
-    resources: Vec<P>,
+    resources: Vec<ResourceConfig<P>>,
+    sample_library: Option<Vec<P>>,
     output: Option<P>,
+    format: Option<OutputFormat>,
+    noise_shaping: Option<bool>,
+    raw: Option<RawFormat>,
+    sample_rate: Option<u32>,
+    inverse_fft: Option<u32>,
+    feedback: Option<bool>,
+    convolution: Option<ConvolutionOptions>,
+    post_chain: Option<PostChainOptions>,
+    oversampling: Option<u32>,
+    spectral: Option<SpectralOptions>,
     silent: Option<f32>,
 }
 
@@ -23,10 +65,34 @@ fn parse_args() -> Option<PlayConfig<String>> {
 		.args(&[
 			Arg::from_usage("[FILE] 'run shader source'"),
 			Arg::from_usage(
-				"-r --resources [FILE].. 'add audio resource, wav is supported.'",
+				"-r --resources [FILE].. 'add audio resource, wav, flac, ogg and mp3 are supported.'",
+			),
+			Arg::from_usage(
+				"-l --sample-library [PATH].. 'add one-shots for sampleFetch, files or directories of them'",
+			),
+			Arg::from_usage(
+				"-o --output [FILE] 'recording wav file. \"-\" writes raw PCM to stdout.'"
 			),
 			Arg::from_usage(
-				"-o --output [FILE] 'recording wav file'"
+				"--raw [FORMAT] 'write raw PCM to stdout instead of playing: f32le (default) or s16le'"
+			),
+			Arg::from_usage(
+				"-f --format [FORMAT] 'output format: wav16, wav24, wav32f, flac or ogg. guessed by the extension by default.'"
+			),
+			Arg::from_usage(
+				"--sample-rate [RATE] 'sample rate of rendering, resampled to the rate of the audio device. the rate of the audio device, or 44100 with --silent by default.'"
+			),
+			Arg::from_usage(
+				"--oversampling [FACTOR] 'render the shader at 2, 4 or 8 times the sample rate and decimate it'"
+			),
+			Arg::from_usage(
+				"--spectral [INPUT] 'play mainSpectral on the STFT of the output of mainSound (default) or of the resource of the index'"
+			),
+			Arg::from_usage(
+				"--feedback 'feed the spectrum and RMS of the previous block of the output back to the shader'"
+			),
+			Arg::from_usage(
+				"--noise-shaping 'apply noise shaping to the dither of integer formats'"
 			),
 			Arg::from_usage("-c --config [FILE] 'read configuration json'"),
 			Arg::from_usage(
@@ -51,11 +117,49 @@ fn parse_args() -> Option<PlayConfig<String>> {
         config.shader_source = filename.to_string();
     }
     if let Some(r) = matches.values_of("resources") {
-        config.resources = r.map(ToString::to_string).collect()
+        config.resources = r
+            .map(|path| ResourceConfig::Path(path.to_string()))
+            .collect()
+    }
+    if let Some(paths) = matches.values_of("sample-library") {
+        config.sample_library = Some(paths.map(str::to_string).collect());
     }
     if let Some(output) = matches.value_of("output") {
         config.output = Some(output.to_string());
     }
+    if let Some(format) = matches.value_of("format") {
+        config.format = Some(format.parse().unwrap_or_else(|e| panic!("{}", e)));
+    }
+    if matches.is_present("feedback") {
+        config.feedback = Some(true);
+    }
+    if matches.is_present("noise-shaping") {
+        config.noise_shaping = Some(true);
+    }
+    if let Some(sample_rate) = matches.value_of("sample-rate") {
+        let sample_rate: u32 = sample_rate.parse().expect("could not parse sample rate");
+        config.sample_rate = Some(sample_rate);
+    }
+    if let Some(factor) = matches.value_of("oversampling") {
+        let factor: u32 = factor.parse().expect("could not parse oversampling factor");
+        config.oversampling = Some(factor);
+    }
+    if matches.is_present("spectral") {
+        let input = match matches.value_of("spectral") {
+            None | Some("sound") => SpectralInput::Sound,
+            Some(index) => {
+                SpectralInput::Resource(index.parse().expect("could not parse resource index"))
+            }
+        };
+        let stft = config.spectral.take().unwrap_or_default().stft;
+        config.spectral = Some(SpectralOptions { stft, input });
+    }
+    if matches.is_present("raw") {
+        let format = matches
+            .value_of("raw")
+            .map_or(Ok(RawFormat::F32LE), str::parse);
+        config.raw = Some(format.unwrap_or_else(|e| panic!("{}", e)));
+    }
     if let Some(seconds) = matches.value_of("silent") {
         let seconds: f32 = seconds.parse().expect("could not parse duration");
         config.silent = Some(seconds);
@@ -74,7 +178,18 @@ fn init() {
         serde_json::to_vec_pretty(&PlayConfig {
             shader_source: "sample.comp",
             resources: Vec::new(),
+            sample_library: None,
             output: None,
+            format: None,
+            noise_shaping: None,
+            raw: None,
+            sample_rate: None,
+            inverse_fft: None,
+            feedback: None,
+            convolution: None,
+            post_chain: None,
+            oversampling: None,
+            spectral: None,
             silent: None,
         })
         .unwrap(),
@@ -82,59 +197,34 @@ fn init() {
     .unwrap();
 }
 
-fn silent<P: AsRef<Path>>(desc: ShaderStreamDescriptor<P>, filename: P, seconds: f32) {
-    use hound::*;
-    let buffer =
-        sound_shader::write_buffer(desc, 44100, std::time::Duration::from_secs_f32(seconds));
-    let spec = WavSpec {
-        channels: 2,
-        sample_rate: 44100,
-        bits_per_sample: 32,
-        sample_format: SampleFormat::Float,
+fn sinks(config: &PlayConfig<String>) -> Vec<Box<dyn AudioSink>> {
+    let mut sinks: Vec<Box<dyn AudioSink>> = Vec::new();
+    let output = config.output.as_ref().filter(|output| *output != "-");
+    let raw = match config.output.as_deref() {
+        Some("-") => Some(config.raw.unwrap_or_default()),
+        _ => config.raw,
     };
-    let mut writer = WavWriter::create(filename, spec).unwrap();
-    buffer
-        .into_iter()
-        .for_each(|s| writer.write_sample(s).unwrap());
-    writer.finalize().unwrap()
-}
-
-fn play<P: AsRef<Path>>(
-    desc: ShaderStreamDescriptor<P>,
-    record_buffer: Option<Arc<Mutex<Vec<f32>>>>,
-    output: Option<P>,
-) {
-    let (stream, stream_config) = sound_shader::stream(desc);
-    cpal::traits::StreamTrait::play(&stream).unwrap();
-
-    let running = Arc::new(AtomicBool::new(true));
-    let running0 = Arc::clone(&running);
-    ctrlc::set_handler(move || running0.store(false, Ordering::SeqCst))
-        .expect("Error setting Ctrl-C handler");
-    println!("Hit CTRL-C to stop playing");
-    while running.load(Ordering::SeqCst) {
-        std::thread::sleep(std::time::Duration::from_millis(10));
-    }
-    drop(stream);
-    if let Some(record_mutex) = record_buffer {
-        use hound::*;
-        let buffer = record_mutex.lock().unwrap();
-        let filename = output.unwrap();
-        let spec = WavSpec {
-            channels: 2,
-            sample_rate: stream_config.sample_rate.0,
-            bits_per_sample: 32,
-            sample_format: SampleFormat::Float,
-        };
-        let mut writer = WavWriter::create(&filename, spec).unwrap_or_else(|_| {
-            panic!(
-                "failed to create output file: {}",
-                filename.as_ref().display()
-            )
-        });
-        buffer.iter().for_each(|s| writer.write_sample(*s).unwrap());
-        writer.finalize().unwrap();
+    match raw {
+        Some(format) => sinks.push(Box::new(StdoutSink::new(format, config.silent.is_none()))),
+        None if config.silent.is_none() => {
+            let device = DeviceSink::new(Default::default()).unwrap();
+            sinks.push(Box::new(device));
+        }
+        None => {}
+    }
+    match output {
+        Some(output) => {
+            let noise_shaping = config.noise_shaping.unwrap_or(false);
+            sinks.push(Box::new(FileSink::new(
+                output,
+                config.format,
+                noise_shaping,
+            )));
+        }
+        None if sinks.is_empty() => panic!("Output wav is not specified."),
+        None => {}
     }
+    sinks
 }
 
 fn main() {
@@ -147,22 +237,40 @@ fn main() {
     };
     let shader_source = std::fs::read_to_string(&config.shader_source)
         .unwrap_or_else(|_| panic!("not found: {}", config.shader_source));
-    let record_buffer = config
-        .output
-        .as_ref()
-        .map(|_| Arc::new(Mutex::new(Vec::new())));
+    let mut sinks = sinks(&config);
+    let resources: Vec<&String> = config.resources.iter().map(ResourceConfig::path).collect();
+    let storage_options: Vec<StorageOptions> = config
+        .resources
+        .iter()
+        .map(ResourceConfig::options)
+        .collect();
+    let sample_library: Vec<&String> = config.sample_library.iter().flatten().collect();
     let desc = ShaderStreamDescriptor {
         audio_device: Default::default(),
         gpu_device: Default::default(),
         shader_source: &shader_source,
-        sound_storages: &config.resources,
-        record_buffer: record_buffer.as_ref().map(Arc::clone),
+        sound_storages: &resources,
+        sound_sources: Vec::new(),
+        storage_options: &storage_options,
+        sample_library: &sample_library,
+        record_buffer: None,
+        record_sender: None,
+        sample_rate: config.sample_rate,
+        inverse_fft: config.inverse_fft,
+        feedback: config.feedback.unwrap_or(false),
+        convolution: config.convolution.clone(),
+        post_chain: config.post_chain.clone(),
+        oversampling: config.oversampling,
+        spectral: config.spectral.clone(),
     };
-    match config.silent {
-        None => play(desc, record_buffer, config.output),
-        Some(seconds) => {
-            let filename = config.output.expect("Output wav is not specified.");
-            silent(desc, filename, seconds);
-        }
+
+    let running = Arc::new(AtomicBool::new(true));
+    let running0 = Arc::clone(&running);
+    ctrlc::set_handler(move || running0.store(false, Ordering::SeqCst))
+        .expect("Error setting Ctrl-C handler");
+    let duration = config.silent.map(Duration::from_secs_f32);
+    if duration.is_none() {
+        eprintln!("Hit CTRL-C to stop playing");
     }
+    sound_shader::run(desc, &mut sinks, duration, &running).unwrap();
 }
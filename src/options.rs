@@ -0,0 +1,280 @@
+use std::path::PathBuf;
+
+/// Interpolation of `soundTexture{n}`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+	/// Linear interpolation between adjacent texels
+	#[default]
+	Linear,
+	/// Cubic Hermite (Catmull-Rom) interpolation over four texels
+	Cubic,
+	/// Hann-windowed sinc over sixteen texels, band-limited when the resource is downsampled
+	Sinc,
+}
+
+/// Window function of the STFT
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Window {
+	/// No window
+	Rectangular,
+	/// Hann window
+	#[default]
+	Hann,
+	/// Blackman window
+	Blackman,
+}
+
+/// Short-time Fourier transform of a resource, fetched by `soundSTFT{n}`
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct StftOptions {
+	/// Frames of each FFT
+	pub size: usize,
+	/// Frames between the ends of adjacent FFTs
+	pub hop: usize,
+	/// Window function
+	pub window: Window,
+}
+
+impl Default for StftOptions {
+	fn default() -> Self {
+		Self {
+			size: 2048,
+			hop: 512,
+			window: Window::default(),
+		}
+	}
+}
+
+/// Input of the STFT processed by `mainSpectral`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpectralInput {
+	/// The dry output of `mainSound` of the same shader
+	#[default]
+	Sound,
+	/// The resource of the index, read by `soundTexture{n}`
+	Resource(usize),
+}
+
+/// Spectral processing of a stream by `mainSpectral`
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SpectralOptions {
+	/// FFT size, hop and window of the STFT around the shader
+	pub stft: StftOptions,
+	/// Stream converted by the STFT
+	pub input: SpectralInput,
+}
+
+/// Convolution with an impulse response, e.g. a reverb
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct ConvolutionOptions {
+	/// Audio file of the impulse response. Mono responses are applied to all channels.
+	/// At most `MAX_IMPULSE_RESPONSE_SECONDS` are loaded.
+	pub impulse_response: PathBuf,
+	/// Gain of the convolved signal
+	pub wet: f32,
+	/// Gain of the original signal
+	pub dry: f32,
+}
+
+impl Default for ConvolutionOptions {
+	fn default() -> Self {
+		Self {
+			impulse_response: PathBuf::new(),
+			wet: 0.3,
+			dry: 1.0,
+		}
+	}
+}
+
+/// Behaviour of a resource after its end
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EndBehaviour {
+	/// Pads silence
+	#[default]
+	Stop,
+	/// Repeats the loop region
+	Loop,
+	/// Holds the last frame
+	Hold,
+}
+
+/// Options for each sound storage
+///
+/// Playback options, from `gain` to `end`, are applied to the files of `sound_storages`.
+/// Other sources can apply them by `TextureMaker::with_playback`.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct StorageOptions {
+	/// Name of the resource. The prelude defines aliases such as `soundTexture_{name}`.
+	pub name: Option<String>,
+	/// Interpolation of `soundTexture{n}`
+	pub interpolation: Interpolation,
+	/// Uploads the whole resource to GPU once, so that shaders can read any position at any
+	/// time. Intended for short resources; at most `MAX_RANDOM_ACCESS_SECONDS` are loaded.
+	pub random_access: bool,
+	/// Amplitude multiplied to the samples
+	pub gain: f32,
+	/// Seconds skipped at the beginning
+	pub start: f32,
+	/// Beginning of the loop region in seconds from the beginning of the resource
+	pub loop_start: f32,
+	/// End of the loop region in seconds. `None` loops until the end of the resource.
+	pub loop_end: Option<f32>,
+	/// Seconds of the crossfade from the end of the loop region to its beginning
+	pub crossfade: f32,
+	/// Behaviour after the end of the resource
+	pub end: EndBehaviour,
+	/// STFT analysis. `None` disables `soundSTFT{n}`.
+	pub stft: Option<StftOptions>,
+	/// Shadertoy-style spectrum and waveform fetched by `soundSpectrum{n}` and `soundWaveform{n}`
+	pub spectrum: bool,
+	/// Envelope, onset strength and beats fetched by `soundEnvelope{n}`, `soundOnset{n}`,
+	/// `soundLastBeat{n}` and `soundNextBeat{n}`. They are analysed on the samples prepared
+	/// in advance by `reserve`, so the beats are known as far as the resource is reserved.
+	pub envelope: bool,
+	/// Fundamental frequency and its confidence fetched by `soundPitch{n}`
+	pub pitch: bool,
+	/// Convolution applied to the resource before the texels are made, with its tail
+	/// after the end of the resource
+	pub convolution: Option<ConvolutionOptions>,
+}
+
+impl Default for StorageOptions {
+	fn default() -> Self {
+		Self {
+			name: None,
+			interpolation: Interpolation::default(),
+			random_access: false,
+			gain: 1.0,
+			start: 0.0,
+			loop_start: 0.0,
+			loop_end: None,
+			crossfade: 0.0,
+			end: EndBehaviour::default(),
+			stft: None,
+			spectrum: false,
+			envelope: false,
+			pitch: false,
+			convolution: None,
+		}
+	}
+}
+
+/// Response of a band of the parametric EQ
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+	/// Boosts or cuts around the frequency
+	#[default]
+	Peak,
+	/// Boosts or cuts below the frequency
+	LowShelf,
+	/// Boosts or cuts above the frequency
+	HighShelf,
+	/// Cuts below the frequency; the gain is ignored.
+	HighPass,
+	/// Cuts above the frequency; the gain is ignored.
+	LowPass,
+}
+
+/// A band of the parametric EQ, a biquad filter
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct EqBand {
+	/// Response of the filter
+	pub filter: Filter,
+	/// Center or cutoff frequency in Hz
+	pub frequency: f32,
+	/// Gain in decibels
+	pub gain: f32,
+	/// Quality factor
+	pub q: f32,
+}
+
+impl Default for EqBand {
+	fn default() -> Self {
+		Self {
+			filter: Filter::default(),
+			frequency: 1000.0,
+			gain: 0.0,
+			q: std::f32::consts::FRAC_1_SQRT_2,
+		}
+	}
+}
+
+/// Feed-forward compressor linked over the channels
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct CompressorOptions {
+	/// Level in decibels above which the gain is reduced
+	pub threshold: f32,
+	/// Ratio of the input level to the output level above the threshold
+	pub ratio: f32,
+	/// Seconds to reduce the gain
+	pub attack: f32,
+	/// Seconds to recover the gain
+	pub release: f32,
+	/// Gain in decibels after the compression
+	pub makeup: f32,
+}
+
+impl Default for CompressorOptions {
+	fn default() -> Self {
+		Self {
+			threshold: -12.0,
+			ratio: 4.0,
+			attack: 0.01,
+			release: 0.1,
+			makeup: 0.0,
+		}
+	}
+}
+
+/// Brickwall limiter with lookahead linked over the channels
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct LimiterOptions {
+	/// Maximum level of the output in decibels
+	pub ceiling: f32,
+	/// Seconds of the lookahead, by which the output is delayed
+	pub lookahead: f32,
+	/// Seconds to recover the gain
+	pub release: f32,
+}
+
+impl Default for LimiterOptions {
+	fn default() -> Self {
+		Self {
+			ceiling: -0.3,
+			lookahead: 0.005,
+			release: 0.05,
+		}
+	}
+}
+
+/// Master chain applied to the output of the shader, in the order of the fields
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct PostChainOptions {
+	/// Removes the DC offset by a high-pass filter at 10 Hz.
+	pub dc_blocker: bool,
+	/// Bands of the parametric EQ
+	pub eq: Vec<EqBand>,
+	/// Compressor. `None` disables it.
+	pub compressor: Option<CompressorOptions>,
+	/// Limiter. `None` disables it.
+	pub limiter: Option<LimiterOptions>,
+}
+
+/// Maximum length of random-access resources
+pub const MAX_RANDOM_ACCESS_SECONDS: usize = 60;
+
+/// Maximum length of impulse responses. The cost of the convolution grows with the length.
+pub const MAX_IMPULSE_RESPONSE_SECONDS: usize = 10;
@@ -0,0 +1,115 @@
+/// Taps of each phase of the decimation filter
+const PHASE_TAPS: usize = 64;
+/// Cutoff frequency of the decimation filter relative to the output sample rate
+const CUTOFF: f64 = 0.45;
+
+/// Frames by which `Decimator` delays the output
+pub(crate) const DECIMATOR_DELAY: usize = PHASE_TAPS / 2;
+
+/// Polyphase decimator of interleaved stereo samples by the Blackman-windowed sinc low-pass filter.
+///
+/// The output frame `n` is the filtered input frame `n * factor`, delayed by `DECIMATOR_DELAY`
+/// frames to make the filter causal.
+pub(crate) struct Decimator {
+	factor: usize,
+	/// `phases[p][j]` is the tap `j * factor + p` of the filter.
+	phases: Vec<Vec<f32>>,
+	/// The last `PHASE_TAPS * factor` input frames
+	history: Vec<[f32; 2]>,
+}
+
+impl Decimator {
+	pub fn new(factor: usize) -> Self {
+		let factor = usize::max(factor, 1);
+		let taps = PHASE_TAPS * factor + 1;
+		let center = (taps / 2) as f64;
+		let cutoff = CUTOFF / factor as f64;
+		let filter: Vec<f64> = (0..taps)
+			.map(|k| {
+				let x = k as f64 - center;
+				let sinc = if x == 0.0 {
+					2.0 * cutoff
+				} else {
+					f64::sin(2.0 * std::f64::consts::PI * cutoff * x) / (std::f64::consts::PI * x)
+				};
+				let t = 2.0 * std::f64::consts::PI * k as f64 / (taps - 1) as f64;
+				sinc * (0.42 - 0.5 * f64::cos(t) + 0.08 * f64::cos(2.0 * t))
+			})
+			.collect();
+		let sum = filter.iter().sum::<f64>();
+		let phases = (0..factor)
+			.map(|p| {
+				filter
+					.iter()
+					.skip(p)
+					.step_by(factor)
+					.map(|h| (h / sum) as f32)
+					.collect()
+			})
+			.collect();
+		Self {
+			factor,
+			phases,
+			history: vec![[0.0; 2]; PHASE_TAPS * factor],
+		}
+	}
+
+	/// Decimates interleaved stereo samples. The number of input frames should be a multiple
+	/// of the factor; the rest is dropped.
+	pub fn process(&mut self, samples: &[f32]) -> Vec<f32> {
+		let Self {
+			factor,
+			phases,
+			history,
+		} = self;
+		let factor = *factor;
+		let offset = history.len();
+		history.extend(samples.chunks_exact(2).map(|x| [x[0], x[1]]));
+		let frames = (history.len() - offset) / factor;
+		let res = (0..frames)
+			.flat_map(|n| {
+				// `history[i]` is the input frame `n * factor`.
+				let i = offset + n * factor;
+				let mut sum = [0.0; 2];
+				phases.iter().enumerate().for_each(|(p, phase)| {
+					phase.iter().enumerate().for_each(|(j, h)| {
+						let x = history[i - j * factor - p];
+						sum[0] += h * x[0];
+						sum[1] += h * x[1];
+					});
+				});
+				sum
+			})
+			.collect();
+		let keep = PHASE_TAPS * factor;
+		history.drain(..history.len() - keep);
+		res
+	}
+}
+
+#[test]
+fn decimator_test() {
+	let factor = 4;
+	let sample_rate = 44100 * factor;
+	let sine = |frequency: f64, i: usize| {
+		f64::sin(2.0 * std::f64::consts::PI * frequency * i as f64 / sample_rate as f64) as f32
+	};
+	// 1 kHz passes, and 30 kHz, which aliases to 14.1 kHz, is removed.
+	let samples: Vec<f32> = (0..sample_rate as usize)
+		.flat_map(|i| vec![sine(1000.0, i), sine(30000.0, i)])
+		.collect();
+	let mut decimator = Decimator::new(factor as usize);
+	let res: Vec<f32> = samples
+		.chunks(1000 * factor as usize * 2)
+		.flat_map(|chunk| decimator.process(chunk))
+		.collect();
+	assert_eq!(res.len(), 44100 * 2);
+	res.chunks(2)
+		.enumerate()
+		.skip(DECIMATOR_DELAY * 2)
+		.for_each(|(n, x)| {
+			let answer = sine(1000.0, (n - DECIMATOR_DELAY) * factor as usize);
+			assert!(f32::abs(x[0] - answer) < 0.001, "{} {:?} {}", n, x, answer);
+			assert!(f32::abs(x[1]) < 0.001, "{} {:?}", n, x);
+		});
+}
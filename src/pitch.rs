@@ -0,0 +1,178 @@
+use crate::options::{StftOptions, Window};
+use crate::stft::StftRows;
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::sync::Arc;
+
+/// Frames between adjacent analyses
+pub(crate) const PITCH_HOP: usize = 512;
+/// Frames of each analysis. The lags up to a half of it are searched.
+const PITCH_SIZE: usize = 2048;
+/// The highest frequency tracked
+const MAX_FREQUENCY: f32 = 2000.0;
+/// Threshold of the cumulative mean normalized difference of a period
+const YIN_THRESHOLD: f32 = 0.15;
+/// RMS below which the frame is regarded as silence
+const MIN_RMS: f32 = 1.0e-4;
+
+/// YIN fundamental frequency tracker of the mean of the first two channels.
+///
+/// Each frame has the frequency in Hz of the period with the smallest lag whose cumulative
+/// mean normalized difference is below the threshold (the global minimum if none), and
+/// the confidence `1 - difference`. Silent frames are `[0.0, 0.0]`.
+pub(crate) struct PitchTracker {
+	stft: StftRows<[f32; 2]>,
+	sample_rate: u32,
+	fft: Arc<dyn Fft<f32>>,
+	ifft: Arc<dyn Fft<f32>>,
+}
+
+impl PitchTracker {
+	pub fn new(sample_rate: u32, channels: usize) -> Self {
+		let options = StftOptions {
+			size: PITCH_SIZE,
+			hop: PITCH_HOP,
+			window: Window::Rectangular,
+		};
+		let mut planner = FftPlanner::new();
+		Self {
+			stft: StftRows::new(&options, channels),
+			sample_rate,
+			fft: planner.plan_fft_forward(PITCH_SIZE),
+			ifft: planner.plan_fft_inverse(PITCH_SIZE),
+		}
+	}
+
+	/// Appends interleaved samples. Returns the index of the first frame, and
+	/// `[frequency, confidence]` of each frame as `Stft::process`.
+	pub fn process(&mut self, samples: &[f32]) -> (usize, Vec<[f32; 2]>) {
+		let Self {
+			stft,
+			sample_rate,
+			fft,
+			ifft,
+		} = self;
+		stft.process(samples, |_, frame_samples, left, right, rows| {
+			let channels = frame_samples.len() / PITCH_SIZE;
+			let mono: Vec<f32> = frame_samples
+				.chunks(channels)
+				.map(|x| (x[0] + x[usize::min(1, channels - 1)]) * 0.5)
+				.collect();
+			rows.push(yin(
+				&mono,
+				left,
+				right,
+				*sample_rate,
+				fft.as_ref(),
+				ifft.as_ref(),
+			));
+		})
+	}
+}
+
+/// Returns `[frequency, confidence]` of `samples`, whose FFTs of the channels are `left` and `right`.
+fn yin(
+	samples: &[f32],
+	left: &[Complex<f32>],
+	right: &[Complex<f32>],
+	sample_rate: u32,
+	fft: &dyn Fft<f32>,
+	ifft: &dyn Fft<f32>,
+) -> [f32; 2] {
+	let width = PITCH_SIZE / 2;
+	let energy = samples[..width].iter().map(|x| x * x).sum::<f32>();
+	if energy < MIN_RMS * MIN_RMS * width as f32 {
+		return [0.0, 0.0];
+	}
+	// `correlation[tau]` is the sum of `samples[j] * samples[j + tau]` for `j < width`,
+	// the cross-correlation of the first half with the whole frame.
+	let mut head: Vec<Complex<f32>> = (0..PITCH_SIZE)
+		.map(|i| {
+			if i < width {
+				Complex::new(samples[i], 0.0)
+			} else {
+				Complex::default()
+			}
+		})
+		.collect();
+	fft.process(&mut head);
+	let mut correlation: Vec<Complex<f32>> = head
+		.iter()
+		.zip(left.iter().zip(right))
+		.map(|(a, (l, r))| a.conj() * (l + r) * 0.5)
+		.collect();
+	ifft.process(&mut correlation);
+	// the energy of `samples[tau..tau + width]`
+	let mut window_energy = energy;
+	let mut sum = 0.0;
+	let differences: Vec<f32> = (0..width)
+		.map(|tau| {
+			if tau > 0 {
+				let (removed, added) = (samples[tau - 1], samples[tau + width - 1]);
+				window_energy += added * added - removed * removed;
+			}
+			let difference = energy + window_energy - 2.0 * correlation[tau].re / PITCH_SIZE as f32;
+			sum += difference;
+			if tau == 0 || sum <= 0.0 {
+				1.0
+			} else {
+				difference * tau as f32 / sum
+			}
+		})
+		.collect();
+	let min_tau = usize::max((sample_rate as f32 / MAX_FREQUENCY) as usize, 2);
+	let tau = match (min_tau..width - 1).find(|tau| differences[*tau] < YIN_THRESHOLD) {
+		// the local minimum after crossing the threshold
+		Some(tau) => (tau..width - 1)
+			.find(|tau| differences[tau + 1] >= differences[*tau])
+			.unwrap_or(width - 2),
+		None => (min_tau..width - 1)
+			.min_by(|a, b| differences[*a].partial_cmp(&differences[*b]).unwrap())
+			.unwrap_or(min_tau),
+	};
+	// parabolic interpolation around the minimum
+	let (d0, d1, d2) = (differences[tau - 1], differences[tau], differences[tau + 1]);
+	let denominator = d0 - 2.0 * d1 + d2;
+	let shift = if denominator > 0.0 {
+		f32::clamp(0.5 * (d0 - d2) / denominator, -0.5, 0.5)
+	} else {
+		0.0
+	};
+	[
+		sample_rate as f32 / (tau as f32 + shift),
+		f32::clamp(1.0 - d1, 0.0, 1.0),
+	]
+}
+
+#[test]
+fn pitch_test() {
+	let sample_rate = 44100;
+	let mut tracker = PitchTracker::new(sample_rate, 2);
+	// a sawtooth of 220 Hz in left and a sine of 220 Hz in right, followed by silence
+	let samples: Vec<f32> = (0..sample_rate as usize)
+		.flat_map(|i| {
+			let phase = 220.0 * i as f32 / sample_rate as f32;
+			let gate = if i < sample_rate as usize / 2 {
+				0.5
+			} else {
+				0.0
+			};
+			vec![
+				gate * (phase.fract() * 2.0 - 1.0),
+				gate * f32::sin(2.0 * std::f32::consts::PI * phase),
+			]
+		})
+		.collect();
+	let mut rows = Vec::new();
+	samples.chunks(2000).for_each(|block| {
+		let (first, res) = tracker.process(block);
+		crate::stft::append_rows(&mut rows, first, &res);
+	});
+	assert_eq!(rows.len(), sample_rate as usize / PITCH_HOP + 1);
+	rows[4..40].iter().for_each(|row| {
+		assert!(f32::abs(row[0] - 220.0) < 1.0, "{:?}", row);
+		assert!(row[1] > 0.9, "{:?}", row);
+	});
+	rows[48..]
+		.iter()
+		.for_each(|row| assert_eq!(*row, [0.0, 0.0]));
+}
@@ -0,0 +1,179 @@
+use crate::options::{EndBehaviour, StorageOptions, MAX_RANDOM_ACCESS_SECONDS};
+use crate::sound_source::SourceSpec;
+
+/// Interleaved samples with the playback options of `StorageOptions` applied.
+pub(crate) struct PlaybackSamples {
+	samples: Box<dyn Iterator<Item = f32> + Send>,
+	channels: usize,
+	gain: f32,
+	end: EndBehaviour,
+	/// Frames skipped at the beginning
+	start: usize,
+	loop_start: usize,
+	loop_end: Option<usize>,
+	crossfade: usize,
+	/// Maximum samples of the loop region
+	max_loop_len: usize,
+	/// Frames read from `samples`
+	position: usize,
+	/// Samples to be returned
+	queue: Vec<f32>,
+	cursor: usize,
+	/// The previous `queue`, kept to reuse its capacity
+	last: Vec<f32>,
+	/// Samples returned repeatedly after `samples` ends
+	repeat: Option<Vec<f32>>,
+}
+
+impl PlaybackSamples {
+	pub fn new(
+		samples: Box<dyn Iterator<Item = f32> + Send>,
+		spec: SourceSpec,
+		options: &StorageOptions,
+	) -> Self {
+		let frames = |seconds: f32| (seconds.max(0.0) * spec.sample_rate as f32) as usize;
+		let start = frames(options.start);
+		Self {
+			samples,
+			channels: spec.channels as usize,
+			gain: options.gain,
+			end: options.end,
+			start,
+			loop_start: usize::max(frames(options.loop_start), start),
+			loop_end: options.loop_end.map(frames),
+			crossfade: frames(options.crossfade),
+			max_loop_len: spec.sample_rate as usize
+				* spec.channels as usize
+				* MAX_RANDOM_ACCESS_SECONDS,
+			position: 0,
+			queue: Vec::new(),
+			cursor: 0,
+			last: Vec::new(),
+			repeat: None,
+		}
+	}
+
+	/// Prepares next samples in `queue`. Returns `false` at the end of playback.
+	fn fill(&mut self) -> bool {
+		std::mem::swap(&mut self.queue, &mut self.last);
+		self.queue.clear();
+		self.cursor = 0;
+		if let Some(repeat) = &self.repeat {
+			self.queue.extend_from_slice(repeat);
+			return !self.queue.is_empty();
+		}
+		if self.position < self.start {
+			let skip = (self.start - self.position) * self.channels;
+			self.samples.by_ref().take(skip).for_each(drop);
+			self.position = self.start;
+		}
+		if self.end == EndBehaviour::Loop && self.position == self.loop_start {
+			return self.read_loop();
+		}
+		self.queue.extend(self.samples.by_ref().take(self.channels));
+		if self.queue.len() == self.channels {
+			self.position += 1;
+			return true;
+		}
+		if self.end == EndBehaviour::Hold && self.last.len() == self.channels {
+			self.queue.extend_from_slice(&self.last);
+			self.repeat = Some(self.last.clone());
+			return true;
+		}
+		false
+	}
+
+	/// Reads the whole loop region. The region is played once, and then the crossfade
+	/// from its end to its beginning and the rest of the region are repeated.
+	fn read_loop(&mut self) -> bool {
+		let len = match self.loop_end {
+			Some(loop_end) => loop_end.saturating_sub(self.loop_start) * self.channels,
+			None => self.max_loop_len,
+		};
+		let region: Vec<f32> = self
+			.samples
+			.by_ref()
+			.take(usize::min(len, self.max_loop_len))
+			.collect();
+		let frames = region.len() / self.channels;
+		if frames == 0 {
+			return false;
+		}
+		let crossfade_frames = usize::min(self.crossfade, frames / 2);
+		let crossfade = crossfade_frames * self.channels;
+		let tail = frames * self.channels - crossfade;
+		let mut repeat: Vec<f32> = (0..crossfade)
+			.map(|i| {
+				let p = ((i / self.channels) as f32 + 0.5) / crossfade_frames as f32;
+				region[tail + i] * (1.0 - p) + region[i] * p
+			})
+			.collect();
+		repeat.extend_from_slice(&region[crossfade..tail]);
+		self.queue.extend_from_slice(&region[..tail]);
+		self.repeat = Some(repeat);
+		true
+	}
+}
+
+impl Iterator for PlaybackSamples {
+	type Item = f32;
+	fn next(&mut self) -> Option<f32> {
+		if self.cursor == self.queue.len() && !self.fill() {
+			return None;
+		}
+		self.cursor += 1;
+		Some(self.queue[self.cursor - 1] * self.gain)
+	}
+	fn size_hint(&self) -> (usize, Option<usize>) {
+		let queued = self.queue.len() - self.cursor;
+		match (self.end, self.samples.size_hint()) {
+			(EndBehaviour::Stop, (_, Some(upper))) => {
+				let skip = self.start.saturating_sub(self.position) * self.channels;
+				let len = queued + upper.saturating_sub(skip);
+				(len, Some(len))
+			}
+			_ => (queued, None),
+		}
+	}
+}
+
+#[test]
+fn playback_test() {
+	let playback = |samples: Vec<f32>, channels: u16, options: StorageOptions| {
+		let spec = SourceSpec {
+			sample_rate: 10,
+			channels,
+		};
+		PlaybackSamples::new(Box::new(samples.into_iter()), spec, &options)
+	};
+	let samples: Vec<f32> = (0..10).map(|i| i as f32).collect();
+	let options = StorageOptions {
+		gain: 0.5,
+		start: 0.4,
+		..Default::default()
+	};
+	let res: Vec<f32> = playback(samples.clone(), 2, options).collect();
+	assert_eq!(res, vec![4.0, 4.5]);
+
+	let options = StorageOptions {
+		end: EndBehaviour::Hold,
+		..Default::default()
+	};
+	let res: Vec<f32> = playback(samples.clone(), 2, options).take(14).collect();
+	assert_eq!(&res[10..], &[8.0, 9.0, 8.0, 9.0]);
+
+	let options = StorageOptions {
+		loop_start: 0.2,
+		loop_end: Some(0.8),
+		crossfade: 0.2,
+		end: EndBehaviour::Loop,
+		..Default::default()
+	};
+	let res: Vec<f32> = playback(samples, 1, options).take(14).collect();
+	let blend = |p: f32| 6.0 * (1.0 - p) + 2.0 * p;
+	let blend1 = |p: f32| 7.0 * (1.0 - p) + 3.0 * p;
+	let cycle = [blend(0.25), blend1(0.75), 4.0, 5.0];
+	let mut answer = vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0];
+	answer.extend(cycle.iter().chain(&cycle));
+	assert_eq!(res, answer);
+}
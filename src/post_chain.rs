@@ -0,0 +1,364 @@
+use crate::options::{CompressorOptions, EqBand, Filter, LimiterOptions, PostChainOptions};
+use std::collections::VecDeque;
+
+/// Cutoff frequency of the DC blocker
+const DC_CUTOFF: f32 = 10.0;
+
+/// Biquad filter of the Audio EQ Cookbook, in the transposed direct form II
+#[derive(Clone, Debug)]
+struct Biquad {
+	/// `[b0, b1, b2, a1, a2]` normalized by `a0`
+	coefficients: [f32; 5],
+	/// States of each channel
+	states: [[f32; 2]; 2],
+}
+
+impl Biquad {
+	fn new(band: &EqBand, sample_rate: u32) -> Self {
+		let w = 2.0 * std::f32::consts::PI * band.frequency / sample_rate as f32;
+		let (sin, cos) = w.sin_cos();
+		let alpha = sin / (2.0 * f32::max(band.q, 1.0e-3));
+		let a = f32::powf(10.0, band.gain / 40.0);
+		let shelf = 2.0 * a.sqrt() * alpha;
+		let [b0, b1, b2, a0, a1, a2] = match band.filter {
+			Filter::Peak => [
+				1.0 + alpha * a,
+				-2.0 * cos,
+				1.0 - alpha * a,
+				1.0 + alpha / a,
+				-2.0 * cos,
+				1.0 - alpha / a,
+			],
+			Filter::LowShelf => [
+				a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+				2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+				a * ((a + 1.0) - (a - 1.0) * cos - shelf),
+				(a + 1.0) + (a - 1.0) * cos + shelf,
+				-2.0 * ((a - 1.0) + (a + 1.0) * cos),
+				(a + 1.0) + (a - 1.0) * cos - shelf,
+			],
+			Filter::HighShelf => [
+				a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+				-2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+				a * ((a + 1.0) + (a - 1.0) * cos - shelf),
+				(a + 1.0) - (a - 1.0) * cos + shelf,
+				2.0 * ((a - 1.0) - (a + 1.0) * cos),
+				(a + 1.0) - (a - 1.0) * cos - shelf,
+			],
+			Filter::HighPass => [
+				(1.0 + cos) / 2.0,
+				-(1.0 + cos),
+				(1.0 + cos) / 2.0,
+				1.0 + alpha,
+				-2.0 * cos,
+				1.0 - alpha,
+			],
+			Filter::LowPass => [
+				(1.0 - cos) / 2.0,
+				1.0 - cos,
+				(1.0 - cos) / 2.0,
+				1.0 + alpha,
+				-2.0 * cos,
+				1.0 - alpha,
+			],
+		};
+		Self {
+			coefficients: [b0 / a0, b1 / a0, b2 / a0, a1 / a0, a2 / a0],
+			states: [[0.0; 2]; 2],
+		}
+	}
+
+	fn process(&mut self, channel: usize, x: f32) -> f32 {
+		let [b0, b1, b2, a1, a2] = self.coefficients;
+		let state = &mut self.states[channel];
+		let y = b0 * x + state[0];
+		state[0] = b1 * x - a1 * y + state[1];
+		state[1] = b2 * x - a2 * y;
+		y
+	}
+}
+
+/// One-pole high-pass filter removing the DC offset
+#[derive(Clone, Debug)]
+struct DcBlocker {
+	pole: f32,
+	/// The last input and output of each channel
+	states: [[f32; 2]; 2],
+}
+
+impl DcBlocker {
+	fn new(sample_rate: u32) -> Self {
+		Self {
+			pole: f32::exp(-2.0 * std::f32::consts::PI * DC_CUTOFF / sample_rate as f32),
+			states: [[0.0; 2]; 2],
+		}
+	}
+
+	fn process(&mut self, channel: usize, x: f32) -> f32 {
+		let state = &mut self.states[channel];
+		let y = x - state[0] + self.pole * state[1];
+		*state = [x, y];
+		y
+	}
+}
+
+/// Returns the coefficient of the one-pole smoothing with the time constant `seconds`.
+fn smoothing(seconds: f32, sample_rate: u32) -> f32 {
+	if seconds > 0.0 {
+		f32::exp(-1.0 / (seconds * sample_rate as f32))
+	} else {
+		0.0
+	}
+}
+
+fn decibels_to_gain(decibels: f32) -> f32 {
+	f32::powf(10.0, decibels / 20.0)
+}
+
+/// Feed-forward compressor with the peak level of the channels
+#[derive(Clone, Debug)]
+struct Compressor {
+	threshold: f32,
+	slope: f32,
+	attack: f32,
+	release: f32,
+	makeup: f32,
+	/// The current gain reduction in decibels
+	reduction: f32,
+}
+
+impl Compressor {
+	fn new(options: &CompressorOptions, sample_rate: u32) -> Self {
+		Self {
+			threshold: options.threshold,
+			slope: 1.0 - 1.0 / f32::max(options.ratio, 1.0),
+			attack: smoothing(options.attack, sample_rate),
+			release: smoothing(options.release, sample_rate),
+			makeup: options.makeup,
+			reduction: 0.0,
+		}
+	}
+
+	/// Returns the gain of a frame.
+	fn gain(&mut self, frame: &[f32]) -> f32 {
+		let peak = frame.iter().fold(0.0, |peak: f32, x| peak.max(x.abs()));
+		let level = 20.0 * f32::log10(f32::max(peak, 1.0e-6));
+		let target = f32::max(level - self.threshold, 0.0) * self.slope;
+		let coefficient = if target > self.reduction {
+			self.attack
+		} else {
+			self.release
+		};
+		self.reduction = target + (self.reduction - target) * coefficient;
+		decibels_to_gain(self.makeup - self.reduction)
+	}
+}
+
+/// Brickwall limiter: the gain is the minimum of the required gains in the lookahead,
+/// smoothed by the moving average over the lookahead, so that it never exceeds the required
+/// gain of the frame output.
+#[derive(Clone, Debug)]
+struct Limiter {
+	ceiling: f32,
+	release: f32,
+	lookahead: usize,
+	/// The required gain released from the last frame
+	released: f32,
+	/// Delayed frames
+	delay: VecDeque<[f32; 2]>,
+	/// `(frame, gain)` of the candidates of the minimum gain, increasing in gain
+	minimums: VecDeque<(usize, f32)>,
+	/// The minimum gains of the recent frames for the moving average
+	held: VecDeque<f32>,
+	sum: f32,
+	position: usize,
+}
+
+impl Limiter {
+	fn new(options: &LimiterOptions, sample_rate: u32) -> Self {
+		let lookahead = usize::max((options.lookahead * sample_rate as f32) as usize, 1);
+		Self {
+			ceiling: decibels_to_gain(options.ceiling),
+			release: smoothing(options.release, sample_rate),
+			lookahead,
+			released: 1.0,
+			delay: vec![[0.0; 2]; lookahead - 1].into(),
+			minimums: VecDeque::new(),
+			held: vec![1.0; lookahead].into(),
+			sum: lookahead as f32,
+			position: 0,
+		}
+	}
+
+	/// Pushes a frame, and returns the frame delayed by `lookahead - 1` frames.
+	fn process(&mut self, frame: [f32; 2]) -> [f32; 2] {
+		let peak = f32::max(frame[0].abs(), frame[1].abs());
+		let required = if peak > self.ceiling {
+			self.ceiling / peak
+		} else {
+			1.0
+		};
+		self.released = f32::min(required, 1.0 - (1.0 - self.released) * self.release);
+		let gain = self.released;
+		while self.minimums.back().is_some_and(|(_, x)| *x >= gain) {
+			self.minimums.pop_back();
+		}
+		self.minimums.push_back((self.position, gain));
+		while self.minimums[0].0 + self.lookahead <= self.position {
+			self.minimums.pop_front();
+		}
+		let held = self.minimums[0].1;
+		self.held.push_back(held);
+		self.sum += held - self.held.pop_front().unwrap();
+		self.position += 1;
+		self.delay.push_back(frame);
+		let frame = self.delay.pop_front().unwrap();
+		// the sum drifts by rounding errors, and the output is clamped to the ceiling.
+		let gain = f32::min(self.sum / self.lookahead as f32, 1.0);
+		let clamp = |x: f32| f32::clamp(x * gain, -self.ceiling, self.ceiling);
+		[clamp(frame[0]), clamp(frame[1])]
+	}
+}
+
+/// Master chain applied to interleaved stereo samples: DC blocker, parametric EQ,
+/// compressor and limiter.
+#[derive(Clone, Debug)]
+pub(crate) struct PostChain {
+	dc_blocker: Option<DcBlocker>,
+	eq: Vec<Biquad>,
+	compressor: Option<Compressor>,
+	limiter: Option<Limiter>,
+}
+
+impl PostChain {
+	pub fn new(options: &PostChainOptions, sample_rate: u32) -> Self {
+		let dc_blocker = if options.dc_blocker {
+			Some(DcBlocker::new(sample_rate))
+		} else {
+			None
+		};
+		Self {
+			dc_blocker,
+			eq: options
+				.eq
+				.iter()
+				.map(|band| Biquad::new(band, sample_rate))
+				.collect(),
+			compressor: options
+				.compressor
+				.as_ref()
+				.map(|options| Compressor::new(options, sample_rate)),
+			limiter: options
+				.limiter
+				.as_ref()
+				.map(|options| Limiter::new(options, sample_rate)),
+		}
+	}
+
+	/// Processes interleaved stereo samples in place.
+	pub fn process(&mut self, samples: &mut [f32]) {
+		samples.chunks_exact_mut(2).for_each(|frame| {
+			(0..2).for_each(|channel| {
+				let mut x = frame[channel];
+				if let Some(dc_blocker) = self.dc_blocker.as_mut() {
+					x = dc_blocker.process(channel, x);
+				}
+				x = self
+					.eq
+					.iter_mut()
+					.fold(x, |x, biquad| biquad.process(channel, x));
+				frame[channel] = x;
+			});
+			if let Some(compressor) = self.compressor.as_mut() {
+				let gain = compressor.gain(frame);
+				frame.iter_mut().for_each(|x| *x *= gain);
+			}
+			if let Some(limiter) = self.limiter.as_mut() {
+				let [left, right] = limiter.process([frame[0], frame[1]]);
+				frame[0] = left;
+				frame[1] = right;
+			}
+		});
+	}
+}
+
+#[test]
+fn post_chain_test() {
+	let sample_rate = 44100;
+	let sine = |frequency: f32, amplitude: f32, offset: f32| -> Vec<f32> {
+		(0..sample_rate)
+			.flat_map(|i| {
+				let t = 2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32;
+				vec![amplitude * t.sin() + offset, amplitude * t.cos() + offset]
+			})
+			.collect()
+	};
+	// the peak level of the last half second
+	let peak = |samples: &[f32]| {
+		samples[samples.len() / 2..]
+			.iter()
+			.fold(0.0, |peak: f32, x| peak.max(x.abs()))
+	};
+
+	let options = PostChainOptions {
+		dc_blocker: true,
+		..Default::default()
+	};
+	let mut samples = sine(1000.0, 0.5, 0.3);
+	PostChain::new(&options, sample_rate).process(&mut samples);
+	assert!(f32::abs(peak(&samples) - 0.5) < 0.01, "{}", peak(&samples));
+
+	let options = PostChainOptions {
+		eq: vec![EqBand {
+			filter: Filter::Peak,
+			frequency: 1000.0,
+			gain: 6.0,
+			q: 1.0,
+		}],
+		..Default::default()
+	};
+	let mut samples = sine(1000.0, 0.25, 0.0);
+	PostChain::new(&options, sample_rate).process(&mut samples);
+	assert!(
+		f32::abs(peak(&samples) - 0.499) < 0.01,
+		"{}",
+		peak(&samples)
+	);
+
+	let options = PostChainOptions {
+		compressor: Some(CompressorOptions {
+			threshold: -12.0,
+			ratio: 4.0,
+			..Default::default()
+		}),
+		..Default::default()
+	};
+	// 0 dB is compressed to -9 dB.
+	let mut samples = sine(1000.0, 1.0, 0.0);
+	PostChain::new(&options, sample_rate).process(&mut samples);
+	let answer = decibels_to_gain(-9.0);
+	assert!(
+		f32::abs(peak(&samples) - answer) < 0.01,
+		"{}",
+		peak(&samples)
+	);
+
+	let options = PostChainOptions {
+		limiter: Some(LimiterOptions::default()),
+		..Default::default()
+	};
+	let mut samples = sine(100.0, 4.0, 0.0);
+	let input = samples.clone();
+	PostChain::new(&options, sample_rate).process(&mut samples);
+	let ceiling = decibels_to_gain(-0.3);
+	assert!(samples.iter().all(|x| x.abs() <= ceiling));
+	assert!(peak(&samples) > ceiling * 0.99);
+	// the output is delayed by the lookahead.
+	let delay = (0.005 * sample_rate as f32) as usize - 1;
+	let mut samples = sine(100.0, 0.5, 0.0);
+	PostChain::new(&options, sample_rate).process(&mut samples);
+	samples[delay * 2..]
+		.iter()
+		.zip(&input)
+		.for_each(|(x, y)| assert!(f32::abs(x - y / 8.0) < 1.0e-6));
+}
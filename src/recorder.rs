@@ -0,0 +1,101 @@
+use crate::encoder::{AudioFileWriter, OutputFormat};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes recorded samples to a file on a background thread.
+///
+/// Samples sent before `start` are queued. The file is flushed every second, so WAV and FLAC
+/// files are valid up to the last flush even if the process is killed. Ogg pages are written
+/// as soon as they are completed.
+#[derive(Debug)]
+pub struct Recorder {
+	sender: Sender<Vec<f32>>,
+	receiver: Option<Receiver<Vec<f32>>>,
+	thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Default for Recorder {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Recorder {
+	/// Creates a recorder which is not writing yet.
+	pub fn new() -> Self {
+		let (sender, receiver) = mpsc::channel();
+		Self {
+			sender,
+			receiver: Some(receiver),
+			thread: None,
+		}
+	}
+
+	/// Returns the sender of interleaved samples, to be set as `record_sender` of a stream.
+	pub fn sender(&self) -> Sender<Vec<f32>> {
+		self.sender.clone()
+	}
+
+	/// Queues interleaved samples.
+	pub fn record(&self, samples: &[f32]) -> Result<(), String> {
+		self.sender
+			.send(samples.to_vec())
+			.map_err(|_| "recording thread is stopped".to_string())
+	}
+
+	/// Creates an audio file by `AudioFileWriter::create` and starts writing to it.
+	///
+	/// The writer is created on the recording thread, since some encoders cannot be moved
+	/// between threads. Errors of the creation are returned here.
+	pub fn start<P: AsRef<Path>>(
+		&mut self,
+		path: P,
+		format: OutputFormat,
+		sample_rate: u32,
+		noise_shaping: bool,
+	) -> Result<(), String> {
+		let receiver = self.receiver.take().expect("recorder is already started");
+		let path = path.as_ref().to_path_buf();
+		let (created_sender, created_receiver) = mpsc::channel();
+		self.thread = Some(std::thread::spawn(move || {
+			let mut writer =
+				match AudioFileWriter::create(&path, format, sample_rate, noise_shaping) {
+					Ok(writer) => writer,
+					Err(e) => {
+						let _ = created_sender.send(Err(e));
+						return Ok(());
+					}
+				};
+			let _ = created_sender.send(Ok(()));
+			let mut flushed = Instant::now();
+			for samples in receiver {
+				writer.write(&samples)?;
+				if flushed.elapsed() > FLUSH_INTERVAL {
+					writer.flush()?;
+					flushed = Instant::now();
+				}
+			}
+			writer.finalize()
+		}));
+		created_receiver
+			.recv()
+			.map_err(|_| "recording thread panicked".to_string())?
+	}
+
+	/// Waits for all senders to be dropped and completes the file.
+	/// The stream must be dropped before calling this.
+	pub fn finish(self) -> Result<(), String> {
+		let Self { sender, thread, .. } = self;
+		drop(sender);
+		match thread {
+			Some(thread) => thread
+				.join()
+				.map_err(|_| "recording thread panicked".to_string())?,
+			None => Ok(()),
+		}
+	}
+}
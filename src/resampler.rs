@@ -0,0 +1,192 @@
+/// Zero crossings of the interpolation kernel on each side
+const ZERO_CROSSINGS: usize = 32;
+/// Cutoff frequency relative to the lower sample rate
+const CUTOFF: f64 = 0.45;
+/// Maximum phases of the kernel tabulated
+const MAX_PHASES: u64 = 1024;
+
+/// Resampler of interleaved stereo samples by the Blackman-windowed sinc interpolation.
+///
+/// The output frame `n` is the input at the time `n / output_rate`, without delay: the kernel
+/// reaches `width` input frames ahead of it, which `input_frames` requires in advance.
+///
+/// The kernel is tabulated for each fractional position of the output frames between the input
+/// frames, as `Decimator`. If the rates have more than `MAX_PHASES` positions, the kernel is
+/// linearly interpolated between `MAX_PHASES` of them.
+pub(crate) struct Resampler {
+	input_rate: u32,
+	output_rate: u32,
+	/// `output_rate` divided by the greatest common divisor of the rates, the number of
+	/// fractional positions of the output frames
+	positions: u64,
+	/// `phases[q]` is the kernel at the position `q / (phases.len() - 1)`, which has the taps of
+	/// the input frames from `1 - width` to `width` relative to the output frame.
+	phases: Vec<Vec<f32>>,
+	/// Input frames on each side of the kernel
+	width: usize,
+	/// Input frames from `offset`
+	buffer: Vec<[f32; 2]>,
+	offset: usize,
+	/// Frames of the input received and the output produced
+	received: usize,
+	produced: usize,
+}
+
+impl Resampler {
+	pub fn new(input_rate: u32, output_rate: u32) -> Self {
+		let ratio = f64::min(output_rate as f64 / input_rate as f64, 1.0);
+		let width = (ZERO_CROSSINGS as f64 / ratio).ceil() as usize;
+		let cutoff = CUTOFF * ratio;
+		let kernel = |x: f64| {
+			if x == 0.0 {
+				2.0 * cutoff
+			} else {
+				let t = std::f64::consts::PI * (x / width as f64 + 1.0);
+				let window = 0.42 - 0.5 * f64::cos(t) + 0.08 * f64::cos(2.0 * t);
+				f64::sin(2.0 * std::f64::consts::PI * cutoff * x) / (std::f64::consts::PI * x)
+					* window
+			}
+		};
+		let positions = output_rate as u64 / gcd(input_rate as u64, output_rate as u64);
+		let count = u64::min(positions, MAX_PHASES);
+		let phases = (0..=count)
+			.map(|q| {
+				let p = q as f64 / count as f64;
+				(0..width * 2)
+					.map(|j| kernel(j as f64 + 1.0 - width as f64 - p) as f32)
+					.collect()
+			})
+			.collect();
+		Self {
+			input_rate,
+			output_rate,
+			positions,
+			phases,
+			width,
+			buffer: Vec::new(),
+			offset: 0,
+			received: 0,
+			produced: 0,
+		}
+	}
+
+	/// Returns the number of input frames required to produce next `frames` frames.
+	pub fn input_frames(&self, frames: usize) -> usize {
+		if frames == 0 {
+			return 0;
+		}
+		let last = (self.produced + frames - 1) as u64 * self.input_rate as u64;
+		let required = (last / self.output_rate as u64) as usize + self.width + 1;
+		required.saturating_sub(self.received)
+	}
+
+	/// Appends interleaved stereo samples of at least `input_frames(frames)` frames,
+	/// and returns next `frames` frames of the output.
+	pub fn process(&mut self, samples: &[f32], frames: usize) -> Vec<f32> {
+		let Self {
+			input_rate,
+			output_rate,
+			positions,
+			phases,
+			width,
+			buffer,
+			offset,
+			received,
+			produced,
+		} = self;
+		let (input_rate, output_rate) = (*input_rate as u64, *output_rate as u64);
+		buffer.extend(samples.chunks_exact(2).map(|x| [x[0], x[1]]));
+		*received += samples.len() / 2;
+		let width = *width as i64;
+		let count = (phases.len() - 1) as u64;
+		let res = (*produced..*produced + frames)
+			.flat_map(|n| {
+				// the time of the output frame in input frames
+				let numerator = n as u64 * input_rate;
+				let idx = (numerator / output_rate) as i64;
+				// the fractional position in `positions`, scaled to the phases
+				let position = numerator % output_rate * *positions / output_rate * count;
+				let q = (position / *positions) as usize;
+				let p = (position % *positions) as f32 / *positions as f32;
+				let mut sum = [0.0; 2];
+				(idx - width + 1..=idx + width)
+					.enumerate()
+					.for_each(|(j, k)| {
+						let x = match k - *offset as i64 {
+							i if i >= 0 && (i as usize) < buffer.len() => buffer[i as usize],
+							_ => [0.0; 2],
+						};
+						let h = if p == 0.0 {
+							phases[q][j]
+						} else {
+							phases[q][j] * (1.0 - p) + phases[q + 1][j] * p
+						};
+						sum[0] += h * x[0];
+						sum[1] += h * x[1];
+					});
+				sum
+			})
+			.collect();
+		*produced += frames;
+		// the input frames before the kernel of the next output are dropped.
+		let next = (*produced as u64 * input_rate / output_rate) as i64 - width;
+		let drop = usize::min((next - *offset as i64).max(0) as usize, buffer.len());
+		buffer.drain(..drop);
+		*offset += drop;
+		res
+	}
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+	if b == 0 {
+		a
+	} else {
+		gcd(b, a % b)
+	}
+}
+
+#[test]
+fn resampler_test() {
+	let sine = |i: usize, sample_rate: u32| {
+		let t = 2.0 * std::f64::consts::PI * 1000.0 * i as f64 / sample_rate as f64;
+		[f64::cos(t) as f32, f64::sin(t) as f32]
+	};
+	for (input_rate, output_rate) in [
+		(44100, 48000),
+		(48000, 44100),
+		(96000, 44100),
+		(44100, 44101),
+	] {
+		let mut resampler = Resampler::new(input_rate, output_rate);
+		let mut res = Vec::new();
+		let mut input = 0;
+		// blocks of a second, and blocks which are not aligned with seconds
+		[output_rate as usize, 1000, 12345, output_rate as usize]
+			.iter()
+			.for_each(|frames| {
+				let len = resampler.input_frames(*frames);
+				let samples: Vec<f32> = (input..input + len)
+					.flat_map(|i| sine(i, input_rate))
+					.collect();
+				input += len;
+				res.extend(resampler.process(&samples, *frames));
+			});
+		assert_eq!(res.len(), (output_rate as usize * 2 + 13345) * 2);
+		// the first frames are filtered with silence before the start.
+		let skip = resampler.width * output_rate as usize / input_rate as usize;
+		res.chunks(2).enumerate().skip(skip).for_each(|(n, x)| {
+			let t = n as f64 / output_rate as f64;
+			let phase = 2.0 * std::f64::consts::PI * 1000.0 * t;
+			let answer = [f64::cos(phase) as f32, f64::sin(phase) as f32];
+			assert!(
+				f32::abs(x[0] - answer[0]) < 0.001 && f32::abs(x[1] - answer[1]) < 0.001,
+				"{} -> {}: {} {:?} {:?}",
+				input_rate,
+				output_rate,
+				n,
+				x,
+				answer
+			);
+		});
+	}
+}
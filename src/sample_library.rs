@@ -0,0 +1,106 @@
+use crate::options::MAX_RANDOM_ACCESS_SECONDS;
+use crate::sound_source::SourceSpec;
+use std::path::{Path, PathBuf};
+
+/// Extensions of the files loaded from directories of a sample library
+const AUDIO_EXTENSIONS: [&str; 4] = ["wav", "flac", "ogg", "mp3"];
+
+/// One-shots packed into a single buffer, fetched by `sampleFetch(int sampleId, float time)`.
+#[derive(Clone, Debug, Default)]
+pub struct SampleLibrary {
+	/// Stereo frames of all samples
+	data: Vec<[f32; 2]>,
+	/// `[offset, frames, sample_rate, 0]` of each sample
+	table: Vec<[u32; 4]>,
+}
+
+impl SampleLibrary {
+	/// Loads audio files in order. Directories are expanded to the audio files in them,
+	/// sorted by file name. Each sample is truncated to `MAX_RANDOM_ACCESS_SECONDS`.
+	pub fn load<P: AsRef<Path>>(paths: &[P]) -> Result<Self, String> {
+		let mut library = Self::default();
+		for path in paths {
+			for file in expand_directory(path.as_ref())? {
+				let (samples, spec) = crate::sound_source::open_samples(&file)
+					.map_err(|e| format!("{}: {}", file.display(), e))?;
+				library.push(spec, samples);
+			}
+		}
+		Ok(library)
+	}
+
+	/// Appends a sample of interleaved samples. Mono samples are played on both channels.
+	pub fn push(&mut self, spec: SourceSpec, samples: impl Iterator<Item = f32>) {
+		let channels = spec.channels as usize;
+		let max_len = spec.sample_rate as usize * channels * MAX_RANDOM_ACCESS_SECONDS;
+		let samples: Vec<f32> = samples.take(max_len).collect();
+		let offset = self.data.len();
+		self.data
+			.extend(samples.chunks_exact(channels).map(|frame| match channels {
+				1 => [frame[0], frame[0]],
+				_ => [frame[0], frame[1]],
+			}));
+		self.table.push([
+			offset as u32,
+			(self.data.len() - offset) as u32,
+			spec.sample_rate,
+			0,
+		]);
+	}
+
+	/// Returns the number of samples.
+	pub fn len(&self) -> usize {
+		self.table.len()
+	}
+
+	/// Returns `true` if the library has no samples.
+	pub fn is_empty(&self) -> bool {
+		self.table.is_empty()
+	}
+
+	pub(crate) fn data(&self) -> &[[f32; 2]] {
+		&self.data
+	}
+
+	pub(crate) fn table(&self) -> &[[u32; 4]] {
+		&self.table
+	}
+}
+
+fn expand_directory(path: &Path) -> Result<Vec<PathBuf>, String> {
+	if !path.is_dir() {
+		return Ok(vec![path.to_path_buf()]);
+	}
+	let mut files = std::fs::read_dir(path)
+		.map_err(|e| format!("{}: {}", path.display(), e))?
+		.filter_map(|entry| entry.ok().map(|entry| entry.path()))
+		.filter(|file| {
+			let extension = file.extension().and_then(|ext| ext.to_str());
+			extension
+				.is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+		})
+		.collect::<Vec<_>>();
+	files.sort();
+	Ok(files)
+}
+
+#[test]
+fn sample_library_test() {
+	let mut library = SampleLibrary::default();
+	let mono = SourceSpec {
+		sample_rate: 22050,
+		channels: 1,
+	};
+	let stereo = SourceSpec {
+		sample_rate: 44100,
+		channels: 2,
+	};
+	library.push(mono, vec![0.1, 0.2, 0.3].into_iter());
+	library.push(stereo, vec![0.4, 0.5, 0.6, 0.7].into_iter());
+	assert_eq!(library.len(), 2);
+	assert_eq!(library.table(), &[[0, 3, 22050, 0], [3, 2, 44100, 0]]);
+	assert_eq!(
+		library.data(),
+		&[[0.1, 0.1], [0.2, 0.2], [0.3, 0.3], [0.4, 0.5], [0.6, 0.7]]
+	);
+}
@@ -0,0 +1,289 @@
+use crate::cpal_wrapper::StreamFactory;
+use crate::encoder::OutputFormat;
+use crate::recorder::Recorder;
+use crate::AudioDevice;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Destination of rendered audio, driven by [`run`](crate::run).
+pub trait AudioSink {
+	/// Returns the sample rate required by the sink, e.g. the rate of an audio device.
+	fn sample_rate(&self) -> Option<u32> {
+		None
+	}
+	/// Called once before the first block with the sample rate of the output.
+	fn start(&mut self, _sample_rate: u32) -> Result<(), String> {
+		Ok(())
+	}
+	/// Receives interleaved stereo samples. Real-time sinks block until there is room,
+	/// which paces the rendering.
+	fn write(&mut self, samples: &[f32]) -> Result<(), String>;
+	/// Called once after the last block.
+	fn finish(&mut self) -> Result<(), String> {
+		Ok(())
+	}
+}
+
+/// Plays on an audio device by `cpal`.
+pub struct DeviceSink {
+	stream: cpal::Stream,
+	config: cpal::StreamConfig,
+	buffer: Arc<Mutex<Vec<f32>>>,
+	playing: bool,
+}
+
+impl DeviceSink {
+	/// Opens the output stream of `audio_device`.
+	pub fn new(audio_device: AudioDevice) -> Result<Self, String> {
+		Self::with_records(audio_device, None, None)
+	}
+
+	/// Opens the output stream of `audio_device`, which also appends the played samples to
+	/// `record_buffer` and sends them to `record_sender`.
+	pub(crate) fn with_records(
+		audio_device: AudioDevice,
+		record_buffer: Option<Arc<Mutex<Vec<f32>>>>,
+		record_sender: Option<Sender<Vec<f32>>>,
+	) -> Result<Self, String> {
+		let sf = match audio_device {
+			AudioDevice::Default => StreamFactory::default_factory()?,
+			AudioDevice::Custum { device, config } => StreamFactory::new(device, config),
+		};
+		let config = sf.config();
+		let buffer = Arc::new(Mutex::new(Vec::new()));
+		let buffer0 = Arc::clone(&buffer);
+		let stream = sf.create_stream(move |len| match buffer0.lock() {
+			Err(e) => {
+				eprintln!("{}", e);
+				vec![0.0; len]
+			}
+			Ok(mut buffer) => {
+				if buffer.len() < len {
+					eprintln!(
+						"buffer length is not enough.\nbuffer length: {}\nrequired: {}",
+						buffer.len(),
+						len
+					);
+					buffer.resize(len, 0.0);
+				}
+				let latter = buffer.split_off(len);
+				let front = std::mem::replace(&mut *buffer, latter);
+				if let Some(record) = record_buffer.as_ref() {
+					match record.try_lock() {
+						Ok(mut record) => record.extend(&front),
+						Err(_) => eprintln!("record buffer is locked"),
+					}
+				}
+				if let Some(sender) = record_sender.as_ref() {
+					if sender.send(front.clone()).is_err() {
+						eprintln!("recorder is stopped");
+					}
+				}
+				front
+			}
+		})?;
+		Ok(Self {
+			stream,
+			config,
+			buffer,
+			playing: false,
+		})
+	}
+
+	/// Returns the stream, which is not played yet, its config and the queue of interleaved
+	/// samples played by the stream, so that another thread can fill the queue.
+	pub(crate) fn into_parts(self) -> (cpal::Stream, cpal::StreamConfig, Arc<Mutex<Vec<f32>>>) {
+		(self.stream, self.config, self.buffer)
+	}
+}
+
+impl AudioSink for DeviceSink {
+	fn sample_rate(&self) -> Option<u32> {
+		Some(self.config.sample_rate.0)
+	}
+	fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+		use cpal::traits::StreamTrait;
+		// keeps at most half a second in the queue before pushing the next block
+		while self.buffer.lock().unwrap().len() >= self.config.sample_rate.0 as usize {
+			std::thread::sleep(Duration::from_millis(10));
+		}
+		self.buffer.lock().unwrap().extend_from_slice(samples);
+		if !self.playing {
+			self.stream.play().map_err(|e| format!("{}", e))?;
+			self.playing = true;
+		}
+		Ok(())
+	}
+	fn finish(&mut self) -> Result<(), String> {
+		use cpal::traits::StreamTrait;
+		while self.playing && !self.buffer.lock().unwrap().is_empty() {
+			std::thread::sleep(Duration::from_millis(10));
+		}
+		self.stream.pause().map_err(|e| format!("{}", e))
+	}
+}
+
+/// Records to an audio file on a background thread.
+pub struct FileSink {
+	path: PathBuf,
+	format: OutputFormat,
+	noise_shaping: bool,
+	recorder: Option<Recorder>,
+}
+
+impl FileSink {
+	/// The file is created by `start`. If `format` is `None`, it is guessed by the extension.
+	pub fn new<P: AsRef<Path>>(path: P, format: Option<OutputFormat>, noise_shaping: bool) -> Self {
+		let path = path.as_ref().to_path_buf();
+		Self {
+			format: format.unwrap_or_else(|| OutputFormat::from_extension(&path)),
+			path,
+			noise_shaping,
+			recorder: None,
+		}
+	}
+}
+
+impl AudioSink for FileSink {
+	fn start(&mut self, sample_rate: u32) -> Result<(), String> {
+		let mut recorder = Recorder::new();
+		recorder
+			.start(&self.path, self.format, sample_rate, self.noise_shaping)
+			.map_err(|e| {
+				format!(
+					"failed to create output file: {}\n{}",
+					self.path.display(),
+					e
+				)
+			})?;
+		self.recorder = Some(recorder);
+		Ok(())
+	}
+	fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+		match self.recorder.as_ref() {
+			Some(recorder) => recorder.record(samples),
+			None => Err("file sink is not started".to_string()),
+		}
+	}
+	fn finish(&mut self) -> Result<(), String> {
+		match self.recorder.take() {
+			Some(recorder) => recorder.finish(),
+			None => Ok(()),
+		}
+	}
+}
+
+/// Discards everything.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+	fn write(&mut self, _: &[f32]) -> Result<(), String> {
+		Ok(())
+	}
+}
+
+/// Sample format of raw PCM
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RawFormat {
+	/// 32-bit float, little endian
+	#[default]
+	F32LE,
+	/// 16-bit signed integer, little endian
+	S16LE,
+}
+
+impl std::fmt::Display for RawFormat {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		match self {
+			Self::F32LE => f.write_str("f32le"),
+			Self::S16LE => f.write_str("s16le"),
+		}
+	}
+}
+
+impl std::str::FromStr for RawFormat {
+	type Err = String;
+	fn from_str(s: &str) -> Result<Self, String> {
+		match s {
+			"f32le" => Ok(Self::F32LE),
+			"s16le" => Ok(Self::S16LE),
+			_ => Err(format!(
+				"unknown raw format: {}\nsupported: f32le, s16le",
+				s
+			)),
+		}
+	}
+}
+
+/// Writes raw interleaved stereo PCM to the standard output.
+///
+/// The format and the sample rate are printed to the standard error on start,
+/// so that downstream tools can be configured.
+#[derive(Debug, Default)]
+pub struct StdoutSink {
+	format: RawFormat,
+	realtime: bool,
+	sample_rate: u32,
+	written_frames: u64,
+	started: Option<Instant>,
+}
+
+impl StdoutSink {
+	/// If `realtime` is `true`, samples are written at the pace of playback.
+	/// Otherwise, as fast as possible.
+	pub fn new(format: RawFormat, realtime: bool) -> Self {
+		Self {
+			format,
+			realtime,
+			..Default::default()
+		}
+	}
+}
+
+impl AudioSink for StdoutSink {
+	fn start(&mut self, sample_rate: u32) -> Result<(), String> {
+		eprintln!(
+			"raw PCM to stdout\nformat: {}\nsample rate: {}\nchannels: 2",
+			self.format, sample_rate
+		);
+		self.sample_rate = sample_rate;
+		self.started = Some(Instant::now());
+		Ok(())
+	}
+	fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+		if let (true, Some(started)) = (self.realtime, self.started) {
+			// keeps half a second ahead of the wall clock
+			let ahead = self.written_frames as f64 / self.sample_rate as f64 - 0.5;
+			let elapsed = started.elapsed().as_secs_f64();
+			if ahead > elapsed {
+				std::thread::sleep(Duration::from_secs_f64(ahead - elapsed));
+			}
+		}
+		let bytes: Vec<u8> = match self.format {
+			RawFormat::F32LE => samples
+				.iter()
+				.flat_map(|s| s.to_le_bytes().to_vec())
+				.collect(),
+			RawFormat::S16LE => samples
+				.iter()
+				.flat_map(|s| {
+					let s = f32::round(s.clamp(-1.0, 1.0) * 32767.0) as i16;
+					s.to_le_bytes().to_vec()
+				})
+				.collect(),
+		};
+		self.written_frames += samples.len() as u64 / 2;
+		std::io::stdout()
+			.lock()
+			.write_all(&bytes)
+			.map_err(|e| format!("{}", e))
+	}
+	fn finish(&mut self) -> Result<(), String> {
+		std::io::stdout().flush().map_err(|e| format!("{}", e))
+	}
+}
@@ -0,0 +1,289 @@
+use crate::convolver::{ConvolvedSamples, Convolver};
+use crate::options::{ConvolutionOptions, StorageOptions};
+use crate::playback::PlaybackSamples;
+use rustfft::{num_complex::Complex, FftPlanner};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Sample rate and channels of a sound source
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SourceSpec {
+	/// Frames per second
+	pub sample_rate: u32,
+	/// The number of interleaved channels
+	pub channels: u16,
+}
+
+/// Frames popped from a sound source
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceBuffer {
+	/// `[left, right, dft.re, dft.im]` of each frame. `left` and `right` are the first two
+	/// channels, and `right` is `0.0` for mono sources.
+	pub texels: Vec<[f32; 4]>,
+	/// Interleaved samples of all channels
+	pub samples: Vec<f32>,
+}
+
+impl SourceBuffer {
+	/// Creates `frames` frames of silence.
+	pub fn silence(frames: usize, channels: usize) -> Self {
+		Self {
+			texels: vec![[0.0; 4]; frames],
+			samples: vec![0.0; frames * channels],
+		}
+	}
+
+	/// Returns the number of frames.
+	pub fn len(&self) -> usize {
+		self.texels.len()
+	}
+
+	/// Returns `true` if the buffer has no frames.
+	pub fn is_empty(&self) -> bool {
+		self.texels.is_empty()
+	}
+
+	/// Appends frames of `other`.
+	pub fn append(&mut self, mut other: Self) {
+		self.texels.append(&mut other.texels);
+		self.samples.append(&mut other.samples);
+	}
+
+	/// Resizes to `frames` frames, padding silence.
+	pub fn resize(&mut self, frames: usize, channels: usize) {
+		self.texels.resize(frames, [0.0; 4]);
+		self.samples.resize(frames * channels, 0.0);
+	}
+
+	/// Returns the frames from `frame` to the end.
+	pub fn tail(&self, frame: usize, channels: usize) -> Self {
+		Self {
+			texels: self.texels[frame..].to_vec(),
+			samples: self.samples[frame * channels..].to_vec(),
+		}
+	}
+}
+
+/// Audio stream which can be fetched by shaders as a sound storage.
+///
+/// Sources are shared with a background thread which calls `reserve` in advance,
+/// and `GPUDirector` pops the texels for each render call by `next_buffer`.
+pub trait SoundSource: Send {
+	/// Returns the format of the samples.
+	fn spec(&self) -> SourceSpec;
+	/// Returns the number of samples prepared in advance.
+	fn buffer_len(&self) -> usize;
+	/// Returns the interleaved samples prepared in advance, which are popped next. Analyses such
+	/// as beat detection run on them ahead of playback. The default is empty, which limits
+	/// the analyses to the popped frames.
+	fn reserved_samples(&self) -> &[f32] {
+		&[]
+	}
+	/// Prepares next `len` samples in advance.
+	fn reserve(&mut self, len: usize);
+	/// Pops `len` frames.
+	fn next_buffer(&mut self, len: usize) -> SourceBuffer;
+	/// Returns `true` if all samples are read and `reserve` only pads silence.
+	fn is_ended(&self) -> bool {
+		false
+	}
+	/// Returns the number of frames of the whole source, if it is known.
+	fn total_frames(&self) -> Option<usize> {
+		None
+	}
+}
+
+/// Makes texels of sound storages from a stream of interleaved samples.
+///
+/// The stream is padded with `0.0` after it ends.
+pub struct TextureMaker {
+	samples: Box<dyn Iterator<Item = f32> + Send>,
+	buffer: Vec<f32>,
+	fft_buffer: Vec<Complex<f32>>,
+	spec: SourceSpec,
+	read_samples: usize,
+	ended: bool,
+}
+
+impl std::fmt::Debug for TextureMaker {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		f.debug_struct("TextureMaker")
+			.field("spec", &self.spec)
+			.field("buffer_len", &self.buffer.len())
+			.finish()
+	}
+}
+
+impl TextureMaker {
+	/// Creates from an iterator of interleaved samples. Returns an error if the source has
+	/// no channels.
+	pub fn new(
+		spec: SourceSpec,
+		samples: impl Iterator<Item = f32> + Send + 'static,
+	) -> Result<Self, String> {
+		if spec.channels == 0 {
+			return Err("unknown channels: 0".to_string());
+		}
+		Ok(Self::from_boxed(spec, Box::new(samples)))
+	}
+
+	/// Creates from a boxed iterator of a validated `spec`.
+	fn from_boxed(spec: SourceSpec, samples: Box<dyn Iterator<Item = f32> + Send>) -> Self {
+		Self {
+			samples,
+			spec,
+			buffer: Vec::new(),
+			fft_buffer: Vec::new(),
+			read_samples: 0,
+			ended: false,
+		}
+	}
+
+	/// Creates from interleaved samples on memory.
+	pub fn from_vec(spec: SourceSpec, samples: Vec<f32>) -> Result<Self, String> {
+		Self::new(spec, samples.into_iter())
+	}
+
+	/// Creates from a generator which returns the next sample for each call.
+	pub fn from_fn(
+		spec: SourceSpec,
+		mut generator: impl FnMut() -> f32 + Send + 'static,
+	) -> Result<Self, String> {
+		Self::new(spec, std::iter::from_fn(move || Some(generator())))
+	}
+
+	/// Opens an audio file. WAV is read by `hound`, and FLAC, Ogg Vorbis and MP3 are decoded
+	/// by `symphonia`.
+	pub fn try_new<P: AsRef<Path>>(filename: P) -> Result<Self, String> {
+		let (samples, spec) = open_samples(filename.as_ref())?;
+		Self::new(spec, samples)
+	}
+
+	/// Applies the playback options: gain, start offset, loop region and end behaviour.
+	/// Must be called before reading samples.
+	pub fn with_playback(self, options: &StorageOptions) -> Self {
+		let Self { samples, spec, .. } = self;
+		let samples = PlaybackSamples::new(samples, spec, options);
+		Self::from_boxed(spec, Box::new(samples))
+	}
+
+	/// Applies the convolution with the impulse response. The tail of the convolution
+	/// is played after the end of the source. The partitions of the impulse response are
+	/// multiplied on `device`. Must be called before reading samples.
+	pub fn with_convolution(
+		self,
+		options: &ConvolutionOptions,
+		device: &Arc<wgpu::Device>,
+		queue: &Arc<wgpu::Queue>,
+	) -> Result<Self, String> {
+		let Self { samples, spec, .. } = self;
+		let channels = spec.channels as usize;
+		let convolver = Convolver::load(options, spec.sample_rate, channels, device, queue)?;
+		let samples = ConvolvedSamples::new(samples, convolver);
+		Ok(Self::from_boxed(spec, Box::new(samples)))
+	}
+}
+
+/// Opens an audio file as interleaved samples, by `hound` for WAV or by `symphonia` for others.
+pub(crate) fn open_samples(
+	path: &Path,
+) -> Result<(Box<dyn Iterator<Item = f32> + Send>, SourceSpec), String> {
+	match crate::hound_wrapper::open_samples(path) {
+		Ok((samples, spec)) => Ok((Box::new(samples), spec)),
+		Err(wav_error) => {
+			let (samples, spec) = crate::symphonia_wrapper::open_samples(path)
+				.map_err(|e| format!("{}\n{}", wav_error, e))?;
+			Ok((Box::new(samples), spec))
+		}
+	}
+}
+
+impl SoundSource for TextureMaker {
+	fn spec(&self) -> SourceSpec {
+		self.spec
+	}
+
+	fn buffer_len(&self) -> usize {
+		self.buffer.len()
+	}
+
+	fn reserved_samples(&self) -> &[f32] {
+		&self.buffer
+	}
+
+	fn reserve(&mut self, len: usize) {
+		let Self {
+			buffer,
+			samples,
+			spec,
+			fft_buffer,
+			read_samples,
+			ended,
+		} = self;
+		buffer.extend((0..len).map(|_| match samples.next() {
+			Some(sample) if !*ended => {
+				*read_samples += 1;
+				sample
+			}
+			_ => {
+				*ended = true;
+				0.0
+			}
+		}));
+		let unit_len = spec.sample_rate as usize / 10;
+		let delta = buffer.len() / spec.channels as usize - fft_buffer.len();
+		if delta > unit_len {
+			let delta = delta - delta % unit_len;
+			let mut planner = FftPlanner::new();
+			let fft = planner.plan_fft_forward(unit_len);
+			let channels = spec.channels as usize;
+			let mut new_buffer: Vec<_> = buffer
+				[fft_buffer.len() * channels..(fft_buffer.len() + delta) * channels]
+				.chunks(channels)
+				.map(|x| Complex {
+					re: x[0],
+					im: x.get(1).copied().unwrap_or(0.0),
+				})
+				.collect();
+			fft.process(&mut new_buffer);
+			fft_buffer.extend(new_buffer);
+		}
+	}
+
+	fn next_buffer(&mut self, len: usize) -> SourceBuffer {
+		let channels = self.spec.channels as usize;
+		if self.buffer.len() < len * channels {
+			self.reserve(len * channels - self.buffer.len());
+		}
+		// the DFT is calculated per unit
+		while self.fft_buffer.len() < len {
+			self.reserve(self.spec.sample_rate as usize / 10 * channels);
+		}
+		let vec = self.buffer.split_off(len * channels);
+		let samples = std::mem::replace(&mut self.buffer, vec);
+		let vec = self.fft_buffer.split_off(len);
+		let dft = std::mem::replace(&mut self.fft_buffer, vec);
+		let texels = samples
+			.chunks(channels)
+			.zip(dft)
+			.map(|(x, y)| [x[0], x.get(1).copied().unwrap_or(0.0), y.re, y.im])
+			.collect();
+		SourceBuffer { texels, samples }
+	}
+
+	fn is_ended(&self) -> bool {
+		self.ended
+	}
+
+	fn total_frames(&self) -> Option<usize> {
+		let remaining = if self.ended {
+			0
+		} else {
+			match self.samples.size_hint() {
+				(lower, Some(upper)) if lower == upper => upper,
+				_ => return None,
+			}
+		};
+		Some((self.read_samples + remaining) / self.spec.channels as usize)
+	}
+}
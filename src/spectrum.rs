@@ -0,0 +1,83 @@
+use crate::options::{StftOptions, Window};
+use crate::stft::StftRows;
+
+/// The number of bins of the spectrum and points of the waveform in each frame
+pub(crate) const SPECTRUM_LEN: usize = 512;
+/// Time constant of the smoothing between frames
+const SMOOTHING: f32 = 0.8;
+/// Decibels mapped to `0.0`
+const MIN_DECIBELS: f32 = -100.0;
+/// Decibels mapped to `1.0`
+const MAX_DECIBELS: f32 = -30.0;
+
+/// Shadertoy-style analyser: the smoothed magnitude spectrum in decibels mapped to `[0, 1]`
+/// and the waveform mapped to `[0, 1]`, of the mean of the first two channels.
+pub(crate) struct SpectrumAnalyser {
+	stft: StftRows<[f32; 2]>,
+	/// Smoothed magnitudes of the last frame
+	smoothed: Vec<f32>,
+}
+
+impl SpectrumAnalyser {
+	pub fn new(channels: usize) -> Self {
+		let options = StftOptions {
+			size: SPECTRUM_LEN * 4,
+			hop: SPECTRUM_LEN,
+			window: Window::Blackman,
+		};
+		Self {
+			stft: StftRows::new(&options, channels),
+			smoothed: vec![0.0; SPECTRUM_LEN],
+		}
+	}
+
+	/// Frames between adjacent analyses
+	pub fn hop(&self) -> usize {
+		self.stft.stft().hop()
+	}
+
+	/// Appends interleaved samples. Returns the index of the first frame, and
+	/// `[spectrum, waveform]` of `SPECTRUM_LEN` points for each frame as `Stft::process`.
+	pub fn process(&mut self, samples: &[f32]) -> (usize, Vec<[f32; 2]>) {
+		let Self { stft, smoothed } = self;
+		let size = stft.stft().size();
+		stft.process(samples, |_, frame_samples, left, right, res| {
+			let channels = frame_samples.len() / size;
+			let step = size / SPECTRUM_LEN;
+			res.extend((0..SPECTRUM_LEN).map(|i| {
+				let magnitude = (left[i] + right[i]).norm() * 0.5 / size as f32;
+				smoothed[i] = SMOOTHING * smoothed[i] + (1.0 - SMOOTHING) * magnitude;
+				let decibels = 20.0 * f32::log10(smoothed[i]);
+				let spectrum = (decibels - MIN_DECIBELS) / (MAX_DECIBELS - MIN_DECIBELS);
+				let x = &frame_samples[i * step * channels..];
+				let sample = (x[0] + x[usize::min(1, channels - 1)]) * 0.5;
+				[
+					f32::clamp(spectrum, 0.0, 1.0),
+					f32::clamp(0.5 + 0.5 * sample, 0.0, 1.0),
+				]
+			}));
+		})
+	}
+}
+
+#[test]
+fn spectrum_test() {
+	let mut analyser = SpectrumAnalyser::new(1);
+	let samples: Vec<f32> = (0..SPECTRUM_LEN * 64)
+		.map(|i| f32::sin(2.0 * std::f32::consts::PI * 64.0 * i as f32 / 2048.0))
+		.collect();
+	let (first, res) = analyser.process(&samples);
+	assert_eq!(first, 0);
+	assert_eq!(res.len(), SPECTRUM_LEN * 64);
+	let (first, row) = analyser.process(&samples[..SPECTRUM_LEN / 2]);
+	assert_eq!(first, 64);
+	assert_eq!(row.len(), SPECTRUM_LEN);
+	// the frame 64 is returned again without smoothing twice.
+	let (first, res) = analyser.process(&samples[..SPECTRUM_LEN / 4]);
+	assert_eq!(first, 64);
+	assert_eq!(res, row);
+	assert!(row[64][0] > 0.9);
+	assert!(row[16][0] < 0.1 && row[200][0] < 0.1);
+	// the frame 64 begins at the frame 30720.
+	assert!(f32::abs(row[1][1] - (0.5 + 0.5 * samples[30720 + 4])) < 0.0001);
+}
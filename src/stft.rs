@@ -0,0 +1,352 @@
+use crate::options::{StftOptions, Window};
+use rustfft::{num_complex::Complex, Fft, FftPlanner};
+use std::sync::Arc;
+
+/// Causal short-time Fourier transform of a stream.
+///
+/// The frame `k` is the FFT of `size` frames of the stream before the frame `k * hop`.
+pub(crate) struct Stft {
+	size: usize,
+	hop: usize,
+	channels: usize,
+	window: Vec<f32>,
+	fft: Arc<dyn Fft<f32>>,
+	/// Interleaved samples of `size + hop` frames before `position`
+	history: Vec<f32>,
+	/// Frames of the stream processed
+	position: usize,
+}
+
+impl Stft {
+	pub fn new(options: &StftOptions, channels: usize) -> Self {
+		let size = usize::max(options.size, 2);
+		let hop = usize::max(options.hop, 1);
+		Self {
+			size,
+			hop,
+			channels,
+			window: (0..size).map(|i| window(options.window, i, size)).collect(),
+			fft: FftPlanner::new().plan_fft_forward(size),
+			history: vec![0.0; (size + hop) * channels],
+			position: 0,
+		}
+	}
+
+	/// Frames of each FFT
+	pub fn size(&self) -> usize {
+		self.size
+	}
+
+	/// Frames between the ends of adjacent FFTs
+	pub fn hop(&self) -> usize {
+		self.hop
+	}
+
+	/// The number of interleaved channels of the stream
+	pub fn channels(&self) -> usize {
+		self.channels
+	}
+
+	/// The number of bins of each frame
+	pub fn bins(&self) -> usize {
+		self.size / 2 + 1
+	}
+
+	/// Appends interleaved samples. Returns the index of the first frame, and the bins
+	/// `[left.re, left.im, right.re, right.im]` of the frames from the last one before `samples`
+	/// to the last one in `samples`. Mono streams have the same left and right spectra.
+	pub fn process(&mut self, samples: &[f32]) -> (usize, Vec<[f32; 4]>) {
+		let bins = self.bins();
+		let mut res = Vec::new();
+		let first = self.process_frames(samples, |_, _, left, right| {
+			res.extend(
+				left.iter()
+					.zip(right)
+					.take(bins)
+					.map(|(l, r)| [l.re, l.im, r.re, r.im]),
+			)
+		});
+		(first, res)
+	}
+
+	/// Appends interleaved samples, and calls `f(frame, samples, left, right)` for each frame
+	/// returned by `process`, with the interleaved samples of the frame before the window.
+	/// Returns the index of the first frame.
+	pub fn process_frames(
+		&mut self,
+		samples: &[f32],
+		mut f: impl FnMut(usize, &[f32], &[Complex<f32>], &[Complex<f32>]),
+	) -> usize {
+		let channels = self.channels;
+		let len = samples.len() / channels;
+		let first = self.position / self.hop;
+		if len == 0 {
+			return first;
+		}
+		self.history.extend_from_slice(&samples[..len * channels]);
+		self.position += len;
+		let last = (self.position - 1) / self.hop;
+		let mut left = vec![Complex::default(); self.size];
+		let mut right = vec![Complex::default(); self.size];
+		for frame in first..=last {
+			// `history` begins at the frame `position - history.len() / channels`.
+			let begin =
+				frame * self.hop + self.history.len() / channels - self.position - self.size;
+			let frame_samples = &self.history[begin * channels..(begin + self.size) * channels];
+			frame_samples
+				.chunks(channels)
+				.zip(&self.window)
+				.enumerate()
+				.for_each(|(i, (x, w))| {
+					left[i] = Complex::new(x[0] * w, 0.0);
+					right[i] = Complex::new(x[usize::min(1, channels - 1)] * w, 0.0);
+				});
+			self.fft.process(&mut left);
+			self.fft.process(&mut right);
+			f(frame, frame_samples, &left, &right);
+		}
+		let keep = (self.size + self.hop) * channels;
+		let excess = self.history.len() - keep;
+		self.history.drain(..excess);
+		first
+	}
+}
+
+/// `Stft` whose frames are analysed into rows of `T`.
+///
+/// `Stft::process_frames` calls back the last frame of the previous call again. Its rows are
+/// kept and returned again, so that analyses with state between frames see each frame once.
+pub(crate) struct StftRows<T> {
+	stft: Stft,
+	/// The last frame and its rows
+	last: Option<(usize, Vec<T>)>,
+}
+
+impl<T: Clone> StftRows<T> {
+	pub fn new(options: &StftOptions, channels: usize) -> Self {
+		Self {
+			stft: Stft::new(options, channels),
+			last: None,
+		}
+	}
+
+	pub fn stft(&self) -> &Stft {
+		&self.stft
+	}
+
+	/// Appends interleaved samples, and calls `f(frame, samples, left, right, rows)` for each new
+	/// frame as `Stft::process_frames`, which pushes the rows of the frame. Returns the index of
+	/// the first frame, and the rows of the frames.
+	pub fn process(
+		&mut self,
+		samples: &[f32],
+		mut f: impl FnMut(usize, &[f32], &[Complex<f32>], &[Complex<f32>], &mut Vec<T>),
+	) -> (usize, Vec<T>) {
+		let Self { stft, last } = self;
+		let mut rows = Vec::new();
+		// the frame and the index of its first row
+		let mut newest = None;
+		let first = stft.process_frames(samples, |frame, frame_samples, left, right| {
+			match last.as_ref() {
+				Some((last_frame, last_rows)) if *last_frame == frame => {
+					rows.extend_from_slice(last_rows)
+				}
+				_ => {
+					newest = Some((frame, rows.len()));
+					f(frame, frame_samples, left, right, &mut rows);
+				}
+			}
+		});
+		if let Some((frame, begin)) = newest {
+			*last = Some((frame, rows[begin..].to_vec()));
+		}
+		(first, rows)
+	}
+}
+
+/// Appends the rows returned by `StftRows::process` from the frame `first` to `rows`, which has
+/// a row for each frame, skipping the frames already in `rows`.
+#[cfg(test)]
+pub(crate) fn append_rows<T: Clone>(rows: &mut Vec<T>, first: usize, res: &[T]) {
+	let skip = rows.len() - first;
+	rows.extend_from_slice(&res[skip..]);
+}
+
+/// Inverse of `Stft` for stereo streams: overlap-adds the inverse FFTs of the frames,
+/// windowed again by the synthesis window.
+///
+/// Each output frame is normalised by the squared windows overlap-added on it, so that the input
+/// is reconstructed for any size, hop and window. Output frames which no window covers are
+/// silent. The output stream is delayed by `size` frames from the input stream of `Stft`.
+pub(crate) struct Istft {
+	size: usize,
+	hop: usize,
+	/// The window divided by `size` and the squared windows overlap-added at each frame
+	synthesis: Vec<f32>,
+	ifft: Arc<dyn Fft<f32>>,
+	/// Spectrum of a channel and the scratch of `ifft`, reused for each frame
+	spectrum: Vec<Complex<f32>>,
+	scratch: Vec<Complex<f32>>,
+	/// Interleaved stereo samples overlap-added from the frame `popped` of the output
+	buffer: Vec<f32>,
+	/// Frames of the output popped
+	popped: usize,
+}
+
+impl Istft {
+	pub fn new(options: &StftOptions) -> Self {
+		let size = usize::max(options.size, 2);
+		let hop = usize::max(options.hop, 1);
+		let window: Vec<f32> = (0..size).map(|i| window(options.window, i, size)).collect();
+		// the output frame `n` is covered by the window positions `i` with `i % hop == n % hop`.
+		let overlap: Vec<f32> = (0..hop)
+			.map(|phase| window.iter().skip(phase).step_by(hop).map(|w| w * w).sum())
+			.collect();
+		let synthesis = window
+			.iter()
+			.enumerate()
+			.map(|(i, w)| {
+				let overlap = overlap[i % hop];
+				if overlap > f32::EPSILON {
+					w / (size as f32 * overlap)
+				} else {
+					0.0
+				}
+			})
+			.collect();
+		let ifft = FftPlanner::new().plan_fft_inverse(size);
+		Self {
+			size,
+			hop,
+			synthesis,
+			spectrum: vec![Complex::default(); size],
+			scratch: vec![Complex::default(); ifft.get_inplace_scratch_len()],
+			ifft,
+			buffer: Vec::new(),
+			popped: 0,
+		}
+	}
+
+	/// Overlap-adds the frame from the bins `[left.re, left.im, right.re, right.im]` returned by
+	/// `Stft::process`. Frames before the popped output are ignored.
+	pub fn add(&mut self, frame: usize, bins: &[[f32; 4]]) {
+		// the frame `k` covers the output frames from `k * hop`.
+		let begin = frame * self.hop;
+		if begin < self.popped {
+			return;
+		}
+		let begin = begin - self.popped;
+		if self.buffer.len() < (begin + self.size) * 2 {
+			self.buffer.resize((begin + self.size) * 2, 0.0);
+		}
+		let Self {
+			size,
+			synthesis,
+			ifft,
+			spectrum,
+			scratch,
+			buffer,
+			..
+		} = self;
+		let size = *size;
+		(0..2).for_each(|channel| {
+			spectrum.fill(Complex::default());
+			bins.iter()
+				.take(size / 2 + 1)
+				.enumerate()
+				.for_each(|(i, x)| {
+					let (re, im) = (x[channel * 2], x[channel * 2 + 1]);
+					spectrum[i] = Complex::new(re, im);
+					if i > 0 && i < size - i {
+						spectrum[size - i] = Complex::new(re, -im);
+					}
+				});
+			ifft.process_with_scratch(spectrum, scratch);
+			spectrum
+				.iter()
+				.zip(synthesis.iter())
+				.enumerate()
+				.for_each(|(i, (x, w))| buffer[(begin + i) * 2 + channel] += x.re * w);
+		});
+	}
+
+	/// Pops `len` frames of interleaved stereo samples.
+	pub fn pop(&mut self, len: usize) -> Vec<f32> {
+		if self.buffer.len() < len * 2 {
+			self.buffer.resize(len * 2, 0.0);
+		}
+		let rest = self.buffer.split_off(len * 2);
+		self.popped += len;
+		std::mem::replace(&mut self.buffer, rest)
+	}
+}
+
+fn window(window: Window, i: usize, size: usize) -> f32 {
+	let x = 2.0 * std::f32::consts::PI * i as f32 / size as f32;
+	match window {
+		Window::Rectangular => 1.0,
+		Window::Hann => 0.5 - 0.5 * f32::cos(x),
+		Window::Blackman => 0.42 - 0.5 * f32::cos(x) + 0.08 * f32::cos(2.0 * x),
+	}
+}
+
+#[test]
+fn stft_test() {
+	let options = StftOptions {
+		size: 64,
+		hop: 32,
+		window: Window::Rectangular,
+	};
+	let mut stft = Stft::new(&options, 2);
+	let samples: Vec<f32> = (0..100)
+		.flat_map(|i| {
+			let t = 2.0 * std::f32::consts::PI * 8.0 * i as f32 / 64.0;
+			vec![f32::cos(t), 0.0]
+		})
+		.collect();
+	let (first, res) = stft.process(&samples);
+	assert_eq!(first, 0);
+	assert_eq!(res.len(), 33 * 4);
+	// the frame 2 is the FFT of the frames `0..64`.
+	let frame = &res[33 * 2..33 * 3];
+	frame.iter().enumerate().for_each(|(bin, x)| {
+		let answer = if bin == 8 { 32.0 } else { 0.0 };
+		assert!(f32::abs(x[0] - answer) < 0.001, "bin {}: {:?}", bin, x);
+		assert!(f32::abs(x[1]) < 0.001 && x[2] == 0.0 && x[3] == 0.0);
+	});
+	let (first, res) = stft.process(&samples[..80]);
+	assert_eq!(first, 3);
+	assert_eq!(res.len(), 33 * 2);
+}
+
+#[test]
+fn istft_test() {
+	// the squared windows overlap-add to a constant only for the first options.
+	let options = [
+		(64, 16, Window::Hann),
+		(64, 32, Window::Hann),
+		(64, 24, Window::Rectangular),
+		(64, 48, Window::Blackman),
+	];
+	for (size, hop, window) in options {
+		let options = StftOptions { size, hop, window };
+		let mut stft = Stft::new(&options, 2);
+		let mut istft = Istft::new(&options);
+		let samples: Vec<f32> = (0..1000)
+			.map(|i| f32::sin(i as f32 * 0.1) * if i % 2 == 0 { 1.0 } else { 0.5 })
+			.collect();
+		let mut res = Vec::new();
+		samples.chunks(70).for_each(|block| {
+			let (first, bins) = stft.process(block);
+			bins.chunks(33)
+				.enumerate()
+				.for_each(|(i, bins)| istft.add(first + i, bins));
+			res.extend(istft.pop(block.len() / 2));
+		});
+		assert_eq!(res.len(), samples.len());
+		// the output is delayed by `size` frames, and restored after the frames overlap.
+		res[64 * 2..].iter().zip(&samples).for_each(|(a, b)| {
+			assert!(f32::abs(a - b) < 0.001, "{:?}: {} {}", options, a, b);
+		});
+	}
+}
@@ -0,0 +1,219 @@
+use crate::sound_source::SourceSpec;
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{Decoder, DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::MetadataOptions;
+use symphonia::core::probe::Hint;
+
+/// Interleaved `f32` samples decoded packet by packet.
+struct DecodedSamples {
+	format: Box<dyn FormatReader>,
+	decoder: Box<dyn Decoder>,
+	track_id: u32,
+	buffer: Vec<f32>,
+	cursor: usize,
+}
+
+impl DecodedSamples {
+	/// Decodes next packet of the track into `buffer`. Returns `false` at the end of stream.
+	fn decode_next(&mut self) -> bool {
+		loop {
+			let packet = match self.format.next_packet() {
+				Ok(packet) => packet,
+				Err(Error::IoError(_)) => return false,
+				Err(e) => {
+					eprintln!("decode error: {}", e);
+					return false;
+				}
+			};
+			if packet.track_id() != self.track_id {
+				continue;
+			}
+			match self.decoder.decode(&packet) {
+				Ok(decoded) => {
+					let mut sample_buffer =
+						SampleBuffer::<f32>::new(decoded.capacity() as u64, *decoded.spec());
+					sample_buffer.copy_interleaved_ref(decoded);
+					self.buffer.clear();
+					self.buffer.extend_from_slice(sample_buffer.samples());
+					self.cursor = 0;
+					if !self.buffer.is_empty() {
+						return true;
+					}
+				}
+				// broken packets are skipped
+				Err(Error::DecodeError(_)) => continue,
+				Err(e) => {
+					eprintln!("decode error: {}", e);
+					return false;
+				}
+			}
+		}
+	}
+}
+
+impl Iterator for DecodedSamples {
+	type Item = f32;
+	fn next(&mut self) -> Option<f32> {
+		if self.cursor == self.buffer.len() && !self.decode_next() {
+			return None;
+		}
+		self.cursor += 1;
+		Some(self.buffer[self.cursor - 1])
+	}
+}
+
+/// Opens FLAC, Ogg Vorbis or MP3 file. The container is detected from the contents,
+/// and the extension is used only as a hint.
+pub fn open_samples(path: &Path) -> Result<(impl Iterator<Item = f32> + Send, SourceSpec), String> {
+	let file = File::open(path).map_err(|e| format!("{}", e))?;
+	let mss = MediaSourceStream::new(Box::new(file), Default::default());
+	let mut hint = Hint::new();
+	if let Some(extension) = path.extension().and_then(|ext| ext.to_str()) {
+		hint.with_extension(extension);
+	}
+	let probed = symphonia::default::get_probe()
+		.format(
+			&hint,
+			mss,
+			// the delay and the padding of encoders are trimmed.
+			&FormatOptions {
+				enable_gapless: true,
+				..Default::default()
+			},
+			&MetadataOptions::default(),
+		)
+		.map_err(|e| format!("{}", e))?;
+	let format = probed.format;
+	let track = format
+		.tracks()
+		.iter()
+		.find(|track| track.codec_params.codec != CODEC_TYPE_NULL)
+		.ok_or("no audio track")?;
+	let decoder = symphonia::default::get_codecs()
+		.make(&track.codec_params, &DecoderOptions::default())
+		.map_err(|e| format!("{}", e))?;
+	let track_id = track.id;
+	let sample_rate = track.codec_params.sample_rate;
+	let channels = track.codec_params.channels.map(|c| c.count() as u16);
+	let n_frames = track.codec_params.n_frames;
+	let mut samples = DecodedSamples {
+		format,
+		decoder,
+		track_id,
+		buffer: Vec::new(),
+		cursor: 0,
+	};
+	// Some formats, e.g. MP3, tell the signal spec only after the first packet is decoded.
+	let (sample_rate, channels) = match (sample_rate, channels) {
+		(Some(sample_rate), Some(channels)) => (sample_rate, channels),
+		_ => {
+			if !samples.decode_next() {
+				return Err("empty audio track".to_string());
+			}
+			let spec = *samples.decoder.last_decoded().spec();
+			(spec.rate, spec.channels.count() as u16)
+		}
+	};
+	let spec = SourceSpec {
+		sample_rate,
+		channels,
+	};
+	// the last block may be padded by encoders, so the samples are truncated to the length
+	// of the track if it is known.
+	let len = n_frames.map_or(usize::MAX, |n_frames| n_frames as usize * channels as usize);
+	Ok((samples.take(len), spec))
+}
+
+#[test]
+fn decode_test() {
+	let dir = std::env::temp_dir().join("sound-shader-decode-test");
+	std::fs::create_dir_all(&dir).unwrap();
+	let sine = |i: usize| f32::sin(2.0 * std::f32::consts::PI * 1000.0 * i as f32 / 44100.0) * 0.5;
+	let decode = |path: &Path| {
+		let (samples, spec) = open_samples(path).unwrap();
+		(samples.collect::<Vec<_>>(), spec)
+	};
+
+	// FLAC of 8-bit and 24-bit PCM, whose last block is padded by the encoder
+	for bits in [8, 24] {
+		use flacenc::component::BitRepr;
+		use flacenc::error::Verify;
+		let scale = f32::powi(2.0, bits - 1);
+		let quantized: Vec<i32> = (0..44100)
+			.flat_map(|i| vec![(sine(i) * scale) as i32, (-sine(i) * scale) as i32])
+			.collect();
+		let config = flacenc::config::Encoder::default().into_verified().unwrap();
+		let source = flacenc::source::MemSource::from_samples(&quantized, 2, bits as usize, 44100);
+		let stream =
+			flacenc::encode_with_fixed_block_size(&config, source, config.block_size).unwrap();
+		let mut sink = flacenc::bitsink::ByteSink::new();
+		stream.write(&mut sink).unwrap();
+		let path = dir.join(format!("sine{}.flac", bits));
+		std::fs::write(&path, sink.as_slice()).unwrap();
+		let (samples, spec) = decode(&path);
+		assert_eq!(
+			spec,
+			SourceSpec {
+				sample_rate: 44100,
+				channels: 2,
+			}
+		);
+		assert_eq!(samples.len(), quantized.len());
+		samples.iter().zip(&quantized).for_each(|(x, q)| {
+			let answer = *q as f32 / scale;
+			assert!(f32::abs(x - answer) < 1.0e-6, "{} {} {}", bits, x, answer);
+		});
+	}
+
+	// Ogg Vorbis, lossy
+	let path = dir.join("sine.ogg");
+	let file = File::create(&path).unwrap();
+	let mut encoder = vorbis_rs::VorbisEncoderBuilder::new(
+		std::num::NonZeroU32::new(44100).unwrap(),
+		std::num::NonZeroU8::new(1).unwrap(),
+		file,
+	)
+	.unwrap()
+	.build()
+	.unwrap();
+	let samples: Vec<f32> = (0..44100).map(sine).collect();
+	encoder.encode_audio_block(&[samples]).unwrap();
+	encoder.finish().unwrap();
+	let (samples, spec) = decode(&path);
+	assert_eq!(
+		spec,
+		SourceSpec {
+			sample_rate: 44100,
+			channels: 1,
+		}
+	);
+	assert_eq!(samples.len(), 44100);
+	samples.iter().enumerate().for_each(|(i, x)| {
+		assert!(f32::abs(x - sine(i)) < 0.02, "{} {} {}", i, x, sine(i));
+	});
+
+	// MP3 of 20 mono frames, each granule of which has only the 5th MDCT line of amplitude 1.0.
+	// The output is periodic by the granule of 576 frames.
+	let path = Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/tone.mp3"));
+	let (samples, spec) = decode(path);
+	assert_eq!(
+		spec,
+		SourceSpec {
+			sample_rate: 44100,
+			channels: 1,
+		}
+	);
+	assert_eq!(samples.len(), 20 * 1152);
+	// the first and the last granules are faded by the overlap of the MDCT.
+	let steady = &samples[1152..samples.len() - 1152];
+	let rms = f32::sqrt(steady.iter().map(|x| x * x).sum::<f32>() / steady.len() as f32);
+	assert!(f32::abs(rms - f32::sqrt(0.5)) < 0.01, "{}", rms);
+	steady.windows(577).for_each(|x| {
+		assert!(f32::abs(x[0] - x[576]) < 1.0e-4, "{} {}", x[0], x[576]);
+	});
+}
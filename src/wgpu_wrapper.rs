@@ -1,4 +1,12 @@
-use crate::hound_wrapper::WavTextureMaker;
+use crate::envelope::{EnvelopeTrack, ENVELOPE_HOP};
+use crate::feedback::{FeedbackAnalyser, FEEDBACK_FFT_SIZE};
+use crate::gpu_fft::GpuInverseFft;
+use crate::options::{Interpolation, SpectralInput, StorageOptions, MAX_RANDOM_ACCESS_SECONDS};
+use crate::pitch::{PitchTracker, PITCH_HOP};
+use crate::sample_library::SampleLibrary;
+use crate::sound_source::{SoundSource, SourceBuffer, SourceSpec};
+use crate::spectrum::{SpectrumAnalyser, SPECTRUM_LEN};
+use crate::stft::Stft;
 use std::sync::{Arc, Mutex};
 use wgpu::{util::DeviceExt, *};
 
@@ -12,6 +20,13 @@ layout(set = 0, binding = 0) buffer OutputStorage {
 layout(set = 0, binding = 1) uniform DeviceInfo {
 	uint iSampleRate;
 	uint iBaseFrame;
+	uint iBlockLength;
+};
+";
+
+const EFFECT_PREFIX: &str = "
+layout(set = 0, binding = 2) buffer InputStorage {
+	vec2[] iInput;
 };
 ";
 
@@ -23,13 +38,345 @@ void main() {
 }
 ";
 
+const EFFECT_SUFFIX: &str = "
+void main() {
+	uint idx = gl_GlobalInvocationID.x;
+	uint frame = iBaseFrame + idx;
+	output[idx] = mainEffect(iInput[idx], idx, float(frame) / float(iSampleRate));
+}
+";
+
+const SPECTRAL_PREFIX: &str = "
+layout(set = 0, binding = 2) buffer SpectralInput {
+	vec4[] iSpectralInput;
+};
+
+layout(set = 0, binding = 3) uniform SpectralInfo {
+	uint iSpectralSize;
+	uint iSpectralHop;
+	uint iSpectralBaseFrame;
+	uint iSpectralFrames;
+};
+";
+
+const SPECTRAL_SUFFIX: &str = "
+void main() {
+	uint bin = gl_GlobalInvocationID.x;
+	uint frame = gl_GlobalInvocationID.y;
+	uint channel = gl_GlobalInvocationID.z;
+	uint idx = frame * (iSpectralSize / 2u + 1u) + bin;
+	vec4 bins = iSpectralInput[idx];
+	vec2 spectrum = bins.xy;
+	if (channel == 1u) {
+		spectrum = bins.zw;
+	}
+	output[idx * 2u + channel] = mainSpectral(bin, iSpectralBaseFrame + frame, spectrum);
+}
+";
+
+const FEEDBACK_PREFIX: &str = "
+layout(set = 0, binding = 4) buffer FeedbackSpectrum {
+	vec2[] iFeedbackSpectrum;
+};
+
+layout(set = 0, binding = 5) uniform FeedbackInfo {
+	vec2 iFeedbackRMS;
+	uint iFeedbackFFTSize;
+};
+
+vec2 feedbackSpectrumFetch(uint bin) {
+	if (bin > iFeedbackFFTSize / 2u) {
+		return vec2(0.0);
+	}
+	return iFeedbackSpectrum[bin];
+}
+vec2 feedbackSpectrum(float freq) {
+	float t = max(freq, 0.0) * float(iFeedbackFFTSize) / float(iSampleRate);
+	uint idx = uint(t);
+	float p = t - float(idx);
+	return feedbackSpectrumFetch(idx) * (1.0 - p) + feedbackSpectrumFetch(idx + 1u) * p;
+}
+";
+
+const INVERSE_FFT_PREFIX: &str = "
+layout(set = 0, binding = 2) buffer InverseFFT {
+	vec2[] iInverseFFT;
+};
+
+layout(set = 0, binding = 3) uniform InverseFFTInfo {
+	uint iInverseFFTSize;
+	uint iInverseFFTBaseFrame;
+	uint iInverseFFTFrames;
+};
+
+vec2 inverseFFTFetch(uint frame) {
+	uint begin = iInverseFFTBaseFrame * iInverseFFTSize;
+	if (frame < begin || frame - begin >= iInverseFFTFrames * iInverseFFTSize) {
+		return vec2(0.0);
+	}
+	return iInverseFFT[frame - begin] / float(iInverseFFTSize);
+}
+";
+
+const INVERSE_FFT_SUFFIX: &str = "
+void main() {
+	uint bin = gl_GlobalInvocationID.x;
+	uint frame = gl_GlobalInvocationID.y;
+	uint base = frame * iInverseFFTSize;
+	vec4 s = mainSpectrum(bin, iInverseFFTBaseFrame + frame);
+	// the spectra of left and right are packed into one FFT as `left + i * right`.
+	output[base + bin] = vec2(s.x - s.w, s.y + s.z);
+	if (bin > 0u && bin < iInverseFFTSize - bin) {
+		output[base + iInverseFFTSize - bin] = vec2(s.x + s.w, s.z - s.y);
+	}
+}
+";
+
+const SAMPLE_LIBRARY_PREFIX: &str = "
+layout(set = 2, binding = 0) buffer SampleData {
+	vec2[] iSampleData;
+};
+
+layout(set = 2, binding = 1) buffer SampleTable {
+	uvec4[] iSampleTable;
+};
+";
+
+/// Returns `main` of the pass rendering the input of `mainSpectral`.
+fn spectral_input_suffix(input: SpectralInput) -> String {
+	let sample = match input {
+		SpectralInput::Sound => "mainSound(idx, time)".to_string(),
+		SpectralInput::Resource(n) => format!("soundTexture{}(time)", n),
+	};
+	format!(
+		"
+void main() {{
+	uint idx = gl_GlobalInvocationID.x;
+	float time = float(iBaseFrame + idx) / float(iSampleRate);
+	output[idx] = {};
+}}
+",
+		sample
+	)
+}
+
+/// Identifiers of each sound storage aliased by the name of the resource
+const ALIASED_IDENTIFIERS: &[&str] = &[
+	"soundTexel",
+	"soundTexture",
+	"soundTexelFetch",
+	"soundDFTFetch",
+	"soundChannelTexel",
+	"soundChannelTexture",
+	"soundChannelFetch",
+	"soundSTFT",
+	"soundSTFTFetch",
+	"soundSpectrum",
+	"soundWaveform",
+	"soundEnvelope",
+	"soundOnset",
+	"soundLastBeat",
+	"soundNextBeat",
+	"soundPitch",
+	"iChannelSampleRate",
+	"iChannelLength",
+	"iChannelTotalLength",
+	"iChannelEnded",
+];
+
+/// The number of texels uploaded before and after each block for interpolation
+const TEXTURE_MARGIN: usize = 8;
+
+/// The number of bindings reserved for each sound storage in the set 1: the texture,
+/// the channels, the info and the buffers of the analyses
+const STORAGE_SLOTS: usize = 7;
+/// Slot of `AudioTextureInfo{n}`, the only uniform buffer
+const INFO_SLOT: usize = 2;
+
+/// Entry point called by the generated `main`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShaderMode {
+	/// `vec2 mainSound(uint samp, float time)`
+	Sound,
+	/// `vec2 mainEffect(vec2 input, uint samp, float time)`
+	Effect,
+	/// `vec2 mainSpectral(uint bin, uint frame, vec2 spectrum)`, with the input rendered on GPU
+	/// by `GPUDirector::render_dry` if it is given
+	Spectral(Option<SpectralInput>),
+}
+
+/// Optional passes and buffers of a shader
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShaderFeatures {
+	/// FFT size of `vec4 mainSpectrum(uint bin, uint frame)`, run before `mainSound`
+	pub inverse_fft: Option<u32>,
+	/// Feeds the analysis of the last output back to the shader, except for
+	/// `ShaderMode::Spectral`
+	pub feedback: bool,
+}
+
+/// STFT frames of a block processed by `mainSpectral`
+pub struct SpectralBlock<'a> {
+	/// FFT size
+	pub size: u32,
+	/// Frames between adjacent STFT frames
+	pub hop: u32,
+	/// The index of the first STFT frame
+	pub base_frame: u32,
+	/// The number of STFT frames
+	pub frames: u32,
+	/// Bins `[left.re, left.im, right.re, right.im]` of each STFT frame as `Stft::process`
+	pub bins: &'a [[f32; 4]],
+}
+
+/// GPU buffers of a sound storage. The buffers of the analyses disabled are not bound.
+struct StorageBuffers {
+	/// `iAudioTexture{n}`
+	texture: Buffer,
+	/// `iAudioChannels{n}`
+	channels: Buffer,
+	/// `AudioTextureInfo{n}`
+	info: Buffer,
+	/// `iAudioSTFT{n}`
+	stft: Option<Buffer>,
+	/// `iAudioSpectrum{n}`
+	spectrum: Option<Buffer>,
+	/// `iAudioEnvelope{n}`
+	envelope: Option<Buffer>,
+	/// `iAudioPitch{n}`
+	pitch: Option<Buffer>,
+}
+
+impl StorageBuffers {
+	/// Returns the buffers in the order of the slots.
+	fn slots(&self) -> [Option<&Buffer>; STORAGE_SLOTS] {
+		[
+			Some(&self.texture),
+			Some(&self.channels),
+			Some(&self.info),
+			self.stft.as_ref(),
+			self.spectrum.as_ref(),
+			self.envelope.as_ref(),
+			self.pitch.as_ref(),
+		]
+	}
+}
+
+/// Sound storage bound to the shader
+pub struct StorageBinding {
+	source: Arc<Mutex<dyn SoundSource>>,
+	options: StorageOptions,
+	/// The last `2 * TEXTURE_MARGIN` frames of the previous upload
+	carry: Option<SourceBuffer>,
+	/// Buffers of the last upload
+	buffers: Option<StorageBuffers>,
+	/// Frames of the source before the current block
+	position: usize,
+	stft: Option<Stft>,
+	spectrum: Option<SpectrumAnalyser>,
+	envelope: Option<EnvelopeTrack>,
+	pitch: Option<PitchTracker>,
+}
+
+impl StorageBinding {
+	pub fn new(source: Arc<Mutex<dyn SoundSource>>, options: StorageOptions) -> Self {
+		let spec = source.lock().unwrap().spec();
+		let channels = spec.channels as usize;
+		let stft = options.stft.as_ref().map(|stft| Stft::new(stft, channels));
+		let spectrum = if options.spectrum {
+			Some(SpectrumAnalyser::new(channels))
+		} else {
+			None
+		};
+		let envelope = if options.envelope {
+			Some(EnvelopeTrack::new(spec.sample_rate, channels))
+		} else {
+			None
+		};
+		let pitch = if options.pitch {
+			Some(PitchTracker::new(spec.sample_rate, channels))
+		} else {
+			None
+		};
+		Self {
+			source,
+			options,
+			carry: None,
+			buffers: None,
+			position: 0,
+			stft,
+			spectrum,
+			envelope,
+			pitch,
+		}
+	}
+
+	/// Returns the whole resource with `TEXTURE_MARGIN` frames before and after.
+	fn bank_texels(&mut self) -> SourceBuffer {
+		let mut source = self.source.lock().unwrap();
+		let spec = source.spec();
+		let channels = spec.channels as usize;
+		let unit_len = spec.sample_rate as usize * channels;
+		let max_len = unit_len * MAX_RANDOM_ACCESS_SECONDS;
+		while !source.is_ended() && source.buffer_len() < max_len {
+			source.reserve(unit_len);
+		}
+		let frames = source.total_frames().unwrap_or(max_len / channels);
+		let mut buffer = SourceBuffer::silence(TEXTURE_MARGIN, channels);
+		buffer.append(source.next_buffer(frames));
+		buffer.resize(frames + 2 * TEXTURE_MARGIN, channels);
+		buffer
+	}
+
+	/// Returns `len` frames of the block with `TEXTURE_MARGIN` frames before and after.
+	fn next_texels(&mut self, len: usize) -> SourceBuffer {
+		let mut source = self.source.lock().unwrap();
+		let channels = source.spec().channels as usize;
+		let mut buffer = match self.carry.take() {
+			Some(carry) => carry,
+			None => {
+				let mut head = SourceBuffer::silence(TEXTURE_MARGIN, channels);
+				head.append(source.next_buffer(TEXTURE_MARGIN));
+				head
+			}
+		};
+		buffer.append(source.next_buffer(len));
+		buffer.resize(len + 2 * TEXTURE_MARGIN, channels);
+		self.carry = Some(buffer.tail(len, channels));
+		buffer
+	}
+}
+
+/// Pipelines of the `mainSpectrum` pass and the inverse FFT before `mainSound`
+struct InverseFftBinding {
+	size: u32,
+	spectrum_pipeline: ComputePipeline,
+	fft: GpuInverseFft,
+}
+
+/// Buffers of the sample library, uploaded once
+struct SampleLibraryBinding {
+	_data: Buffer,
+	_table: Buffer,
+	bind_group: BindGroup,
+}
+
 pub struct GPUDirector {
 	device: Arc<Device>,
 	queue: Arc<Queue>,
 	bind_group_layouts: Vec<BindGroupLayout>,
 	pipeline: ComputePipeline,
 	base_frame: u32,
-	sound_storages: Vec<Arc<Mutex<WavTextureMaker>>>,
+	sound_storages: Vec<StorageBinding>,
+	sample_library: Option<SampleLibraryBinding>,
+	inverse_fft: Option<InverseFftBinding>,
+	/// Analysis of the last output, if `ShaderFeatures::feedback` is enabled
+	feedback: Option<FeedbackAnalyser>,
+	mode: ShaderMode,
+	/// Pipeline rendering the input of `mainSpectral`
+	dry_pipeline: Option<ComputePipeline>,
+	/// The sound storages are uploaded for the current block by `render_dry`.
+	dry_rendered: bool,
 }
 
 impl GPUDirector {
@@ -37,10 +384,48 @@ impl GPUDirector {
 		device: Arc<Device>,
 		queue: Arc<Queue>,
 		shader_source: &str,
-		sound_storages: Vec<Arc<Mutex<WavTextureMaker>>>,
+		sound_storages: Vec<StorageBinding>,
+		sample_library: Option<&SampleLibrary>,
+		mode: ShaderMode,
+		features: ShaderFeatures,
 	) -> Self {
-		let bind_group_layouts = create_bind_group_layouts(&device, sound_storages.len());
-		let pipeline = read_source(&device, &bind_group_layouts, shader_source, &sound_storages);
+		let bind_group_layouts = create_bind_group_layouts(
+			&device,
+			&sound_storages,
+			sample_library.is_some(),
+			mode,
+			features,
+		);
+		let prelude = shader_prelude(&sound_storages, sample_library, mode, features);
+		let suffix = match mode {
+			ShaderMode::Sound => SHADER_SUFFIX,
+			ShaderMode::Effect => EFFECT_SUFFIX,
+			ShaderMode::Spectral(_) => SPECTRAL_SUFFIX,
+		};
+		let code = prelude.clone() + shader_source + suffix;
+		let pipeline = read_source(&device, &bind_group_layouts, &code);
+		let dry_pipeline = match mode {
+			ShaderMode::Spectral(Some(input)) => {
+				let code = prelude.clone() + shader_source + &spectral_input_suffix(input);
+				Some(read_source(&device, &bind_group_layouts, &code))
+			}
+			_ => None,
+		};
+		let inverse_fft = features.inverse_fft.map(|size| {
+			let code = prelude + shader_source + INVERSE_FFT_SUFFIX;
+			InverseFftBinding {
+				size,
+				spectrum_pipeline: read_source(&device, &bind_group_layouts, &code),
+				fft: GpuInverseFft::new(&device),
+			}
+		});
+		let sample_library = sample_library
+			.map(|library| upload_sample_library(&device, &bind_group_layouts[2], library));
+		let feedback = if features.feedback {
+			Some(FeedbackAnalyser::new())
+		} else {
+			None
+		};
 		Self {
 			device,
 			queue,
@@ -48,21 +433,53 @@ impl GPUDirector {
 			pipeline,
 			base_frame: 0,
 			sound_storages,
+			sample_library,
+			inverse_fft,
+			feedback,
+			mode,
+			dry_pipeline,
+			dry_rendered: false,
 		}
 	}
-	pub fn from_default_device(
-		shader_source: &str,
-		sound_storages: Vec<Arc<Mutex<WavTextureMaker>>>,
-	) -> Self {
-		let (device, queue) = init_device();
-		Self::new(
-			Arc::new(device),
-			Arc::new(queue),
-			shader_source,
-			sound_storages,
-		)
-	}
 	pub fn render(&mut self, sample_rate: u32, buffer_length: u32) -> Vec<f32> {
+		assert_eq!(self.mode, ShaderMode::Sound, "effect shader requires input");
+		self.dispatch(sample_rate, buffer_length / 2, None, None)
+	}
+	/// Runs `mainEffect` over the interleaved stereo block `input`.
+	pub fn process(&mut self, sample_rate: u32, input: &[f32]) -> Vec<f32> {
+		assert_eq!(self.mode, ShaderMode::Effect, "sound shader takes no input");
+		self.dispatch(sample_rate, input.len() as u32 / 2, Some(input), None)
+	}
+	/// Renders the input of `mainSpectral` for the next block of `block_length` frames.
+	/// The next `process_spectral` reuses the sound storages uploaded for it.
+	pub fn render_dry(&mut self, sample_rate: u32, block_length: u32) -> Vec<f32> {
+		assert!(
+			self.dry_pipeline.is_some(),
+			"spectral shader without input requires blocks"
+		);
+		self.dispatch(sample_rate, block_length, None, None)
+	}
+	/// Runs `mainSpectral` over the STFT frames of a block of `block_length` frames.
+	/// Returns the bins in the layout of `block.bins`.
+	pub fn process_spectral(
+		&mut self,
+		sample_rate: u32,
+		block_length: u32,
+		block: &SpectralBlock,
+	) -> Vec<f32> {
+		assert!(
+			matches!(self.mode, ShaderMode::Spectral(_)),
+			"spectral shader requires STFT"
+		);
+		self.dispatch(sample_rate, block_length, None, Some(block))
+	}
+	fn dispatch(
+		&mut self,
+		sample_rate: u32,
+		block_length: u32,
+		input: Option<&[f32]>,
+		spectral: Option<&SpectralBlock>,
+	) -> Vec<f32> {
 		let Self {
 			ref device,
 			ref queue,
@@ -70,53 +487,180 @@ impl GPUDirector {
 			ref pipeline,
 			ref mut base_frame,
 			ref mut sound_storages,
+			ref sample_library,
+			ref inverse_fft,
+			ref mut feedback,
+			ref dry_pipeline,
+			ref mut dry_rendered,
+			..
 		} = self;
-		let (storage, staging) = create_output_buffers(&self.device, buffer_length as u64);
+		// the dry pass and the spectral pass of a block share the frames and the storages.
+		let dry = dry_pipeline.is_some() && input.is_none() && spectral.is_none();
+		let block_base_frame = *base_frame;
 		let device_info = device.create_buffer_init(&util::BufferInitDescriptor {
 			label: None,
-			contents: bytemuck::cast_slice(&[sample_rate, *base_frame]),
+			contents: bytemuck::cast_slice(&[sample_rate, *base_frame, block_length, 0]),
 			usage: BufferUsages::UNIFORM,
 		});
-		*base_frame += buffer_length / 2;
-		let bind_group0 = device.create_bind_group(&BindGroupDescriptor {
-			label: None,
-			layout: &bind_group_layouts[0],
-			entries: &[
-				BindGroupEntry {
-					binding: 0,
-					resource: storage.as_entire_binding(),
-				},
-				BindGroupEntry {
-					binding: 1,
-					resource: device_info.as_entire_binding(),
-				},
-			],
+		if !dry {
+			*base_frame += block_length;
+		}
+		if !*dry_rendered {
+			upload_sound_storages(device, sound_storages, block_length as usize, sample_rate);
+		}
+		*dry_rendered = dry;
+		// the output is a stereo frame for each invocation, or a bin for each channel
+		// of each STFT frame.
+		let (output_length, workgroups) = match spectral {
+			Some(block) => {
+				let bins = block.size / 2 + 1;
+				(bins * block.frames * 4, (bins, block.frames, 2))
+			}
+			None => (block_length * 2, (block_length, 1, 1)),
+		};
+		// empty buffers cannot be bound.
+		if output_length == 0 {
+			return Vec::new();
+		}
+		let (storage, staging) = create_output_buffers(device, output_length as u64);
+		let input = input.or_else(|| spectral.map(|block| bytemuck::cast_slice(block.bins)));
+		let input_storage = input.map(|input| {
+			device.create_buffer_init(&util::BufferInitDescriptor {
+				label: None,
+				contents: bytemuck::cast_slice(input),
+				usage: BufferUsages::STORAGE,
+			})
+		});
+		let spectral_info = spectral.map(|block| {
+			device.create_buffer_init(&util::BufferInitDescriptor {
+				label: None,
+				contents: bytemuck::cast_slice(&[
+					block.size,
+					block.hop,
+					block.base_frame,
+					block.frames,
+				]),
+				usage: BufferUsages::UNIFORM,
+			})
 		});
-		let sound_buffers =
-			sound_storage_buffers(device, sound_storages, buffer_length as usize / 2, sample_rate);
-		let entries = buffers_to_entries(&sound_buffers);
+		let entries = sound_storage_entries(sound_storages);
 		let bind_group1 = device.create_bind_group(&BindGroupDescriptor {
 			label: None,
 			layout: &bind_group_layouts[1],
 			entries: &entries,
 		});
+		let feedback_buffers = feedback.as_ref().map(|feedback| {
+			let spectrum = device.create_buffer_init(&util::BufferInitDescriptor {
+				label: None,
+				contents: bytemuck::cast_slice(feedback.spectrum()),
+				usage: BufferUsages::STORAGE,
+			});
+			let [left, right] = feedback.rms();
+			let info = device.create_buffer_init(&util::BufferInitDescriptor {
+				label: None,
+				contents: bytemuck::cast_slice(&[
+					left.to_bits(),
+					right.to_bits(),
+					FEEDBACK_FFT_SIZE as u32,
+					0,
+				]),
+				usage: BufferUsages::UNIFORM,
+			});
+			[spectrum, info]
+		});
+		// bindings from 2 are optional; see `create_bind_group_layouts`.
+		let create_bind_group0 = |buffers: &[(u32, &Buffer)]| {
+			let feedback_buffers = feedback_buffers
+				.iter()
+				.flat_map(|[spectrum, info]| vec![(4, spectrum), (5, info)]);
+			let entries: Vec<BindGroupEntry> = buffers
+				.iter()
+				.map(|(binding, buffer)| BindGroupEntry {
+					binding: *binding,
+					resource: buffer.as_entire_binding(),
+				})
+				.chain(feedback_buffers.map(|(binding, buffer)| BindGroupEntry {
+					binding,
+					resource: buffer.as_entire_binding(),
+				}))
+				.collect();
+			device.create_bind_group(&BindGroupDescriptor {
+				label: None,
+				layout: &bind_group_layouts[0],
+				entries: &entries,
+			})
+		};
+		let synthesis = inverse_fft.as_ref().map(|inverse_fft| {
+			InverseFftBlock::new(device, inverse_fft.size, block_base_frame, block_length)
+		});
+		// the dry pass binds empty STFT frames to the layout of `mainSpectral`.
+		let dry_buffers = if dry {
+			let buffer = |usage| {
+				device.create_buffer_init(&util::BufferInitDescriptor {
+					label: None,
+					contents: &[0; 16],
+					usage,
+				})
+			};
+			Some([buffer(BufferUsages::STORAGE), buffer(BufferUsages::UNIFORM)])
+		} else {
+			None
+		};
+		let mut buffers0 = vec![(0, &storage), (1, &device_info)];
+		buffers0.extend(input_storage.as_ref().map(|buffer| (2, buffer)));
+		buffers0.extend(spectral_info.as_ref().map(|buffer| (3, buffer)));
+		buffers0.extend(
+			dry_buffers
+				.iter()
+				.flat_map(|[input, info]| [(2, input), (3, info)]),
+		);
 		let mut encoder = device.create_command_encoder(&Default::default());
-		{
-			let mut cpass = encoder.begin_compute_pass(&Default::default());
-			cpass.set_pipeline(pipeline);
-			cpass.set_bind_group(0, &bind_group0, &[]);
-			cpass.set_bind_group(1, &bind_group1, &[]);
-			cpass.insert_debug_marker("rendering sound");
-			cpass.dispatch(buffer_length / 2, 1, 1);
+		// `mainSpectrum` and the inverse FFT are run before `mainSound`.
+		if let (Some(inverse_fft), Some(synthesis)) = (inverse_fft, synthesis.as_ref()) {
+			let [spectrum, work] = &synthesis.buffers;
+			let bind_group0 = create_bind_group0(&[
+				(0, spectrum),
+				(1, &device_info),
+				(2, work),
+				(3, &synthesis.info),
+			]);
+			encode_pass(
+				&mut encoder,
+				&inverse_fft.spectrum_pipeline,
+				[&bind_group0, &bind_group1],
+				sample_library.as_ref(),
+				(inverse_fft.size / 2 + 1, synthesis.frames, 1),
+			);
+			let result = inverse_fft.fft.encode(
+				device,
+				&mut encoder,
+				[spectrum, work],
+				inverse_fft.size,
+				synthesis.frames,
+			);
+			buffers0.push((2, &synthesis.buffers[result]));
+			buffers0.push((3, &synthesis.info));
 		}
-		encoder.copy_buffer_to_buffer(&storage, 0, &staging, 0, buffer_length as u64 * 4);
+		let bind_group0 = create_bind_group0(&buffers0);
+		let pipeline = match dry_pipeline {
+			Some(dry_pipeline) if dry => dry_pipeline,
+			_ => pipeline,
+		};
+		encode_pass(
+			&mut encoder,
+			pipeline,
+			[&bind_group0, &bind_group1],
+			sample_library.as_ref(),
+			workgroups,
+		);
+		encoder.copy_buffer_to_buffer(&storage, 0, &staging, 0, output_length as u64 * 4);
 		queue.submit(Some(encoder.finish()));
 
 		let buffer_slice = staging.slice(..);
 		let buffer_future = buffer_slice.map_async(MapMode::Read);
 		device.poll(wgpu::Maintain::Wait);
 
-		pollster::block_on(async {
+		let result = pollster::block_on(async {
 			if buffer_future.await.is_ok() {
 				let data = buffer_slice.get_mapped_range();
 				let result: Vec<f32> = bytemuck::cast_slice(&data).to_vec();
@@ -126,68 +670,220 @@ impl GPUDirector {
 			} else {
 				panic!("failed to run compute on gpu");
 			}
-		})
+		});
+		if let Some(feedback) = feedback.as_mut() {
+			feedback.analyse(&result);
+		}
+		result
 	}
 }
 
-fn init_device() -> (Device, Queue) {
+/// Buffers of the spectra synthesized by `mainSpectrum` for a block
+struct InverseFftBlock {
+	/// The spectra, and the work buffer of the inverse FFT
+	buffers: [Buffer; 2],
+	/// `InverseFFTInfo`
+	info: Buffer,
+	/// The number of FFT frames overlapping the block
+	frames: u32,
+}
+
+impl InverseFftBlock {
+	fn new(device: &Device, size: u32, base_frame: u32, block_length: u32) -> Self {
+		let begin = base_frame / size;
+		let frames = (base_frame + block_length).div_ceil(size) - begin;
+		let buffer = || {
+			device.create_buffer(&BufferDescriptor {
+				label: None,
+				size: size as u64 * frames as u64 * 8,
+				usage: BufferUsages::STORAGE,
+				mapped_at_creation: false,
+			})
+		};
+		let info = device.create_buffer_init(&util::BufferInitDescriptor {
+			label: None,
+			contents: bytemuck::cast_slice(&[size, begin, frames, 0]),
+			usage: BufferUsages::UNIFORM,
+		});
+		Self {
+			buffers: [buffer(), buffer()],
+			info,
+			frames,
+		}
+	}
+}
+
+fn encode_pass(
+	encoder: &mut CommandEncoder,
+	pipeline: &ComputePipeline,
+	bind_groups: [&BindGroup; 2],
+	sample_library: Option<&SampleLibraryBinding>,
+	workgroups: (u32, u32, u32),
+) {
+	let mut cpass = encoder.begin_compute_pass(&Default::default());
+	cpass.set_pipeline(pipeline);
+	cpass.set_bind_group(0, bind_groups[0], &[]);
+	cpass.set_bind_group(1, bind_groups[1], &[]);
+	if let Some(sample_library) = sample_library {
+		cpass.set_bind_group(2, &sample_library.bind_group, &[]);
+	}
+	cpass.insert_debug_marker("rendering sound");
+	cpass.dispatch(workgroups.0, workgroups.1, workgroups.2);
+}
+
+pub(crate) fn init_device() -> (Device, Queue) {
 	let instance = Instance::new(Backends::PRIMARY);
 	pollster::block_on(async {
 		let adaptor = instance
 			.request_adapter(&Default::default())
 			.await
 			.expect("failed to find an appropriate adapter");
+		// each resource binds from two to six storage buffers, depending on its analyses.
+		let descriptor = DeviceDescriptor {
+			limits: adaptor.limits(),
+			..Default::default()
+		};
 		adaptor
-			.request_device(&Default::default(), None)
+			.request_device(&descriptor, None)
 			.await
 			.expect("failed to create device")
 	})
 }
 
-fn create_bind_group_layouts(device: &Device, len: usize) -> Vec<BindGroupLayout> {
-	let bgl0 = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-		label: None,
-		entries: &[
-			BindGroupLayoutEntry {
-				binding: 0,
-				visibility: ShaderStages::COMPUTE,
-				ty: BindingType::Buffer {
-					ty: BufferBindingType::Storage { read_only: false },
-					has_dynamic_offset: false,
-					min_binding_size: None,
-				},
-				count: None,
+fn create_bind_group_layouts(
+	device: &Device,
+	storages: &[StorageBinding],
+	sample_library: bool,
+	mode: ShaderMode,
+	features: ShaderFeatures,
+) -> Vec<BindGroupLayout> {
+	let inverse_fft = features.inverse_fft.is_some();
+	let mut entries0 = vec![
+		BindGroupLayoutEntry {
+			binding: 0,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Storage { read_only: false },
+				has_dynamic_offset: false,
+				min_binding_size: None,
 			},
-			BindGroupLayoutEntry {
-				binding: 1,
-				visibility: ShaderStages::COMPUTE,
-				ty: BindingType::Buffer {
-					ty: BufferBindingType::Uniform,
-					has_dynamic_offset: false,
-					min_binding_size: None,
-				},
-				count: None,
+			count: None,
+		},
+		BindGroupLayoutEntry {
+			binding: 1,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: None,
 			},
-		],
+			count: None,
+		},
+	];
+	if mode != ShaderMode::Sound || inverse_fft {
+		entries0.push(BindGroupLayoutEntry {
+			binding: 2,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Storage { read_only: false },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		});
+	}
+	if matches!(mode, ShaderMode::Spectral(_)) || inverse_fft {
+		entries0.push(BindGroupLayoutEntry {
+			binding: 3,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		});
+	}
+	if features.feedback {
+		entries0.push(BindGroupLayoutEntry {
+			binding: 4,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Storage { read_only: false },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		});
+		entries0.push(BindGroupLayoutEntry {
+			binding: 5,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		});
+	}
+	let bgl0 = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+		label: None,
+		entries: &entries0,
 	});
 	let bgl1 = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
 		label: None,
-		entries: &sound_storage_bind_group_layout_entries(len as u32),
+		entries: &sound_storage_bind_group_layout_entries(storages),
+	});
+	let mut layouts = vec![bgl0, bgl1];
+	if sample_library {
+		layouts.push(device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+			label: None,
+			entries: &sample_library_bind_group_layout_entries(),
+		}));
+	}
+	layouts
+}
+
+fn shader_prelude(
+	resources: &[StorageBinding],
+	sample_library: Option<&SampleLibrary>,
+	mode: ShaderMode,
+	features: ShaderFeatures,
+) -> String {
+	let mut code_buf = SHADER_PREFIX.to_string();
+	match mode {
+		ShaderMode::Sound => {}
+		ShaderMode::Effect => code_buf += EFFECT_PREFIX,
+		ShaderMode::Spectral(_) => code_buf += SPECTRAL_PREFIX,
+	}
+	if features.feedback {
+		code_buf += FEEDBACK_PREFIX;
+	}
+	if features.inverse_fft.is_some() {
+		code_buf += INVERSE_FFT_PREFIX;
+	}
+	resources.iter().enumerate().for_each(|(idx, resource)| {
+		code_buf += &sound_storage_bindingshader(idx, &resource.options);
 	});
-	vec![bgl0, bgl1]
+	resources.iter().enumerate().for_each(|(idx, resource)| {
+		code_buf += &sound_storage_fetchfunction(idx, &resource.options);
+		if let Some(name) = &resource.options.name {
+			code_buf += &sound_storage_aliases(idx, name);
+		}
+	});
+	if let Some(sample_library) = sample_library {
+		code_buf += SAMPLE_LIBRARY_PREFIX;
+		code_buf += &sample_library_fetchfunction(sample_library.len());
+	}
+	code_buf
 }
 
 pub fn read_source(
 	device: &Device,
 	bind_group_layouts: &[BindGroupLayout],
 	code: &str,
-	resources: &[Arc<Mutex<WavTextureMaker>>],
 ) -> ComputePipeline {
-	let mut code_buf = SHADER_PREFIX.to_string();
-	(0..resources.len()).for_each(|idx| code_buf += &sound_storage_bindingshader(idx));
-	(0..resources.len()).for_each(|idx| code_buf += &sound_storage_fetchfunction(idx));
-	code_buf = code_buf + code + SHADER_SUFFIX;
-	let wgsl = glsl_to_wgsl(&code_buf);
+	let wgsl = glsl_to_wgsl(code);
 	let module = device.create_shader_module(&ShaderModuleDescriptor {
 		label: None,
 		source: ShaderSource::Wgsl(wgsl.into()),
@@ -205,7 +901,7 @@ pub fn read_source(
 	})
 }
 
-fn glsl_to_wgsl(code: &str) -> String {
+pub(crate) fn glsl_to_wgsl(code: &str) -> String {
 	let glsl_module = naga::front::glsl::Parser::default()
 		.parse(
 			&naga::front::glsl::Options {
@@ -232,6 +928,172 @@ fn glsl_to_wgsl_test() {
 	println!("{}", code);
 }
 
+#[test]
+fn glsl_to_wgsl_interpolation_test() {
+	let mut code = SHADER_PREFIX.to_string();
+	[
+		Interpolation::Linear,
+		Interpolation::Cubic,
+		Interpolation::Sinc,
+	]
+	.iter()
+	.enumerate()
+	.for_each(|(idx, interpolation)| {
+		let options = StorageOptions {
+			interpolation: *interpolation,
+			random_access: idx == 2,
+			stft: Some(Default::default()),
+			spectrum: idx != 1,
+			envelope: true,
+			pitch: idx == 2,
+			..Default::default()
+		};
+		code += &sound_storage_bindingshader(idx, &options);
+		code += &sound_storage_fetchfunction(idx, &options);
+	});
+	code += "vec2 mainSound(uint samp, float time) {
+	vec2 res = soundTexture0(time) + soundTexture1(time) + soundTexture2(time);
+	res += soundSTFT1(time, 440.0).xz + soundSTFTFetch2(0u, 1u).yw;
+	res += vec2(soundSpectrum0(time, 0.25), soundWaveform1(time, 0.5));
+	res *= soundEnvelope2(time) + soundOnset0(time) * exp(soundLastBeat1(time) - time);
+	res *= 1.0 - exp(time - soundNextBeat0(time));
+	res += sin(6.283185 * soundPitch2(time).x * time) * soundPitch2(time).y;
+	return res + vec2(soundChannelTexture0(time, 2), soundChannelFetch2(samp, 3));
+}";
+	code += SHADER_SUFFIX;
+	let code = glsl_to_wgsl(&code);
+	println!("{}", code);
+}
+
+#[test]
+fn sound_storage_slots_test() {
+	assert_eq!(sound_storage_slots(&Default::default()), vec![0, 1, 2]);
+	let options = StorageOptions {
+		spectrum: true,
+		pitch: true,
+		..Default::default()
+	};
+	assert_eq!(sound_storage_slots(&options), vec![0, 1, 2, 4, 6]);
+	// only the enabled analyses are declared.
+	let code = sound_storage_bindingshader(1, &options);
+	assert!(code.contains("binding = 11) buffer AudioSpectrum1"));
+	assert!(code.contains("binding = 13) buffer AudioPitch1"));
+	assert!(!code.contains("AudioSTFT1") && !code.contains("AudioEnvelope1"));
+}
+
+#[test]
+fn resource_names_test() {
+	let code = SHADER_PREFIX.to_string()
+		+ &sound_storage_bindingshader(0, &Default::default())
+		+ &sound_storage_fetchfunction(0, &Default::default())
+		+ &sound_storage_aliases(0, "vocal")
+		+ "vec2 mainSound(uint samp, float time) {
+	return soundTexture_vocal(time) * float(iChannelEnded_vocal);
+}" + SHADER_SUFFIX;
+	let wgsl = glsl_to_wgsl(&code);
+	println!("{}", wgsl);
+
+	let resource = |name: &str| {
+		let source = crate::sound_source::TextureMaker::from_vec(
+			SourceSpec {
+				sample_rate: 44100,
+				channels: 1,
+			},
+			Vec::new(),
+		)
+		.unwrap();
+		let options = StorageOptions {
+			name: Some(name.to_string()),
+			..Default::default()
+		};
+		StorageBinding::new(Arc::new(Mutex::new(source)), options)
+	};
+	assert!(check_resource_names(&code, &[resource("vocal")]).is_ok());
+	assert!(check_resource_names(&code, &[resource("inst")]).is_err());
+	assert!(check_resource_names(&code, &[resource("vocal"), resource("vocal")]).is_err());
+	assert!(check_resource_names(&code, &[resource("vocal"), resource("a-b")]).is_err());
+}
+
+#[test]
+fn glsl_to_wgsl_effect_test() {
+	let code = SHADER_PREFIX.to_string()
+		+ EFFECT_PREFIX
+		+ "vec2 mainEffect(vec2 input, uint samp, float time) {
+	return (input + iInput[(samp + 1) % iBlockLength]) * 0.5;
+}" + EFFECT_SUFFIX;
+	let code = glsl_to_wgsl(&code);
+	println!("{}", code);
+}
+
+#[test]
+fn glsl_to_wgsl_spectral_test() {
+	let code = SHADER_PREFIX.to_string()
+		+ SPECTRAL_PREFIX
+		+ "vec2 mainSpectral(uint bin, uint frame, vec2 spectrum) {
+	float freq = float(bin) * float(iSampleRate) / float(iSpectralSize);
+	return spectrum * (1.0 - smoothstep(1000.0, 2000.0, freq));
+}" + SPECTRAL_SUFFIX;
+	let code = glsl_to_wgsl(&code);
+	println!("{}", code);
+}
+
+#[test]
+fn glsl_to_wgsl_spectral_input_test() {
+	let code = SHADER_PREFIX.to_string()
+		+ SPECTRAL_PREFIX
+		+ "vec2 mainSound(uint samp, float time) {
+	return vec2(sin(6.2831853 * 440.0 * time));
+}
+vec2 mainSpectral(uint bin, uint frame, vec2 spectrum) {
+	return spectrum;
+}" + &spectral_input_suffix(SpectralInput::Sound);
+	let code = glsl_to_wgsl(&code);
+	println!("{}", code);
+}
+
+#[test]
+fn glsl_to_wgsl_feedback_test() {
+	let code = SHADER_PREFIX.to_string()
+		+ FEEDBACK_PREFIX
+		+ "vec2 mainSound(uint samp, float time) {
+	vec2 ducking = 1.0 / (1.0 + 4.0 * iFeedbackRMS);
+	vec2 level = feedbackSpectrum(440.0) + feedbackSpectrumFetch(samp % 1025u);
+	return vec2(sin(6.2831 * 440.0 * time)) * ducking * (1.0 - level);
+}" + SHADER_SUFFIX;
+	let code = glsl_to_wgsl(&code);
+	println!("{}", code);
+}
+
+#[test]
+fn glsl_to_wgsl_inverse_fft_test() {
+	let user_code = "vec4 mainSpectrum(uint bin, uint frame) {
+	if (bin == 8u) {
+		return vec4(float(iInverseFFTSize) * 0.5, 0.0, 0.0, float(frame % 2u));
+	}
+	return vec4(0.0);
+}
+vec2 mainSound(uint samp, float time) {
+	return inverseFFTFetch(iBaseFrame + samp);
+}";
+	let prelude = SHADER_PREFIX.to_string() + INVERSE_FFT_PREFIX;
+	let code = glsl_to_wgsl(&(prelude.clone() + user_code + SHADER_SUFFIX));
+	println!("{}", code);
+	let code = glsl_to_wgsl(&(prelude + user_code + INVERSE_FFT_SUFFIX));
+	println!("{}", code);
+}
+
+#[test]
+fn glsl_to_wgsl_sample_library_test() {
+	let code = SHADER_PREFIX.to_string()
+		+ SAMPLE_LIBRARY_PREFIX
+		+ &sample_library_fetchfunction(2)
+		+ "vec2 mainSound(uint samp, float time) {
+	return sampleFetch(0, time) + sampleFetch(1, time * 0.5) * sampleLength(1);
+}" + SHADER_SUFFIX;
+	let code = glsl_to_wgsl(&code);
+	println!("{}", code);
+}
+
 fn create_output_buffers(device: &Device, len: u64) -> (Buffer, Buffer) {
 	let storage = device.create_buffer(&BufferDescriptor {
 		label: None,
@@ -248,118 +1110,656 @@ fn create_output_buffers(device: &Device, len: u64) -> (Buffer, Buffer) {
 	(storage, staging)
 }
 
-fn sound_storage_bindingshader(idx: usize) -> String {
-	format!(
+/// Returns the slots bound for the resource: the texture, the channels, the info and the
+/// buffers of the analyses enabled by `options`.
+fn sound_storage_slots(options: &StorageOptions) -> Vec<usize> {
+	let analyses = [
+		options.stft.is_some(),
+		options.spectrum,
+		options.envelope,
+		options.pitch,
+	];
+	(0..STORAGE_SLOTS)
+		.filter(|slot| *slot <= INFO_SLOT || analyses[slot - INFO_SLOT - 1])
+		.collect()
+}
+
+fn sound_storage_bindingshader(idx: usize, options: &StorageOptions) -> String {
+	let binding = |slot: usize| idx * STORAGE_SLOTS + slot;
+	let mut code = format!(
 		"layout(set = 1, binding = {}) buffer AudioTexture{} {{
 	vec4[] iAudioTexture{1};
 }};
+layout(set = 1, binding = {}) buffer AudioChannels{1} {{
+	float[] iAudioChannels{1};
+}};
 layout(set = 1, binding = {}) uniform AudioTextureInfo{1} {{
 	uint iChannelSampleRate{1};
 	uint channels{1};
+	uint iChannelLength{1};
+	uint iChannelTotalLength{1};
+	uint iChannelEnded{1};
+	uint iSTFTSize{1};
+	uint iSTFTHop{1};
+	uint iSTFTBaseFrame{1};
+	uint iSTFTFrames{1};
+	uint iSpectrumHop{1};
+	uint iSpectrumBaseFrame{1};
+	uint iSpectrumFrames{1};
+	uint iEnvelopeBaseFrame{1};
+	uint iEnvelopeFrames{1};
+	uint iBeatCount{1};
+	uint iPitchBaseFrame{1};
+	uint iPitchFrames{1};
 }};
-	",
-		idx * 2,
+",
+		binding(0),
 		idx,
-		idx * 2 + 1
-	)
+		binding(1),
+		binding(INFO_SLOT),
+	);
+	// the buffers of the analyses disabled are not bound, which saves storage buffers.
+	let analyses = [
+		("AudioSTFT", "vec4"),
+		("AudioSpectrum", "vec2"),
+		("AudioEnvelope", "vec2"),
+		("AudioPitch", "vec2"),
+	];
+	sound_storage_slots(options)
+		.into_iter()
+		.filter(|slot| *slot > INFO_SLOT)
+		.for_each(|slot| {
+			let (name, ty) = analyses[slot - INFO_SLOT - 1];
+			code += &format!(
+				"layout(set = 1, binding = {}) buffer {}{} {{
+	{}[] i{1}{2};
+}};
+",
+				binding(slot),
+				name,
+				idx,
+				ty
+			);
+		});
+	code
 }
 
-fn sound_storage_fetchfunction(idx: usize) -> String {
-	format!(
-		"vec2 soundTexture{}(float time) {{
-	float t = time - float(iBaseFrame) / float(iSampleRate);
-	uint idx = uint(float(iChannelSampleRate{0}) * t);
-	float p = fract(float(iChannelSampleRate{0}) * t);
-	return iAudioTexture{0}[idx].xy * (1.0 - p) + iAudioTexture{0}[idx + 1].xy * p;
+fn sound_storage_fetchfunction(idx: usize, options: &StorageOptions) -> String {
+	// random-access resources are indexed from the beginning of the resource.
+	let (time_offset, base_idx) = if options.random_access {
+		("0.0".to_string(), "0u".to_string())
+	} else {
+		(
+			"float(iBaseFrame) / float(iSampleRate)".to_string(),
+			format!("iChannelSampleRate{} * (iBaseFrame / iSampleRate)", idx),
+		)
+	};
+	let interpolation = match options.interpolation {
+		Interpolation::Linear => format!(
+			"	return soundTexel{0}(idx) * (1.0 - p) + soundTexel{0}(idx + 1) * p;",
+			idx
+		),
+		Interpolation::Cubic => format!(
+			"	vec2 y0 = soundTexel{0}(idx - 1);
+	vec2 y1 = soundTexel{0}(idx);
+	vec2 y2 = soundTexel{0}(idx + 1);
+	vec2 y3 = soundTexel{0}(idx + 2);
+	vec2 c1 = 0.5 * (y2 - y0);
+	vec2 c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+	vec2 c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+	return ((c3 * p + c2) * p + c1) * p + y1;",
+			idx
+		),
+		Interpolation::Sinc => format!(
+			"	float cutoff = min(1.0, float(iSampleRate) / float(iChannelSampleRate{0}));
+	vec2 sum = vec2(0.0);
+	for (int k = 1 - {1}; k <= {1}; k++) {{
+		float x = float(k) - p;
+		float window = 0.5 + 0.5 * cos(3.14159265359 * x / float({1}));
+		float sinc = 1.0;
+		if (abs(x) > 0.000001) {{
+			sinc = sin(3.14159265359 * x * cutoff) / (3.14159265359 * x * cutoff);
+		}}
+		sum += soundTexel{0}(idx + k) * (cutoff * sinc * window);
+	}}
+	return sum;",
+			idx, TEXTURE_MARGIN
+		),
+	};
+	sound_storage_analysisfunction(idx, options)
+		+ &format!(
+			"vec2 soundTexel{0}(int idx) {{
+	int len = int(iChannelLength{0});
+	return iAudioTexture{0}[uint(clamp(idx + {1}, 0, len - 1))].xy;
+}}
+vec2 soundTexture{0}(float time) {{
+	float t = (time - {3}) * float(iChannelSampleRate{0});
+	int idx = int(floor(t));
+	float p = t - float(idx);
+{2}
 }}
 vec2 soundTexelFetch{0}(uint idx) {{
-	uint baseIdx = iChannelSampleRate{0} * (iBaseFrame / iSampleRate);
-	return iAudioTexture{0}[idx - baseIdx].xy;
+	uint baseIdx = {4};
+	return iAudioTexture{0}[idx - baseIdx + {1}u].xy;
 }}
 vec2 soundDFTFetch{0}(uint idx) {{
-	uint baseIdx = iChannelSampleRate{0} * (iBaseFrame / iSampleRate);
-	return iAudioTexture{0}[idx - baseIdx].zw;
+	uint baseIdx = {4};
+	return iAudioTexture{0}[idx - baseIdx + {1}u].zw;
+}}
+float soundChannelTexel{0}(int idx, int channel) {{
+	if (channel < 0 || channel >= int(channels{0})) {{
+		return 0.0;
+	}}
+	int len = int(iChannelLength{0});
+	uint frame = uint(clamp(idx + {1}, 0, len - 1));
+	return iAudioChannels{0}[frame * channels{0} + uint(channel)];
+}}
+float soundChannelTexture{0}(float time, int channel) {{
+	float t = (time - {3}) * float(iChannelSampleRate{0});
+	int idx = int(floor(t));
+	float p = t - float(idx);
+	return soundChannelTexel{0}(idx, channel) * (1.0 - p) + soundChannelTexel{0}(idx + 1, channel) * p;
+}}
+float soundChannelFetch{0}(uint idx, int channel) {{
+	uint baseIdx = {4};
+	return soundChannelTexel{0}(int(idx - baseIdx), channel);
+}}
+vec4 soundSTFT{0}(float time, float freq) {{
+	if (iSTFTSize{0} == 0u) {{
+		return vec4(0.0);
+	}}
+	uint frame = uint(max(time, 0.0) * float(iChannelSampleRate{0})) / iSTFTHop{0};
+	float bin = freq * float(iSTFTSize{0}) / float(iChannelSampleRate{0});
+	return soundSTFTFetch{0}(frame, uint(max(floor(bin + 0.5), 0.0)));
+}}
+float soundSpectrum{0}(float time, float freq01) {{
+	return soundSpectrumRow{0}(time, freq01).x;
+}}
+float soundWaveform{0}(float time, float x01) {{
+	return soundSpectrumRow{0}(time, x01).y;
+}}
+vec2 soundEnvelopeRow{0}(float time) {{
+	float t = max(time, 0.0) * float(iChannelSampleRate{0}) / float({5});
+	int idx = int(floor(t));
+	float p = t - float(idx);
+	return soundEnvelopeTexel{0}(idx) * (1.0 - p) + soundEnvelopeTexel{0}(idx + 1) * p;
+}}
+float soundEnvelope{0}(float time) {{
+	return soundEnvelopeRow{0}(time).x;
+}}
+float soundOnset{0}(float time) {{
+	return soundEnvelopeRow{0}(time).y;
+}}
+vec2 soundPitch{0}(float time) {{
+	float t = max(time, 0.0) * float(iChannelSampleRate{0}) / float({6});
+	int idx = int(floor(t));
+	vec2 a = soundPitchTexel{0}(idx);
+	vec2 b = soundPitchTexel{0}(idx + 1);
+	// frequencies are not interpolated from or to unvoiced frames.
+	if (a.x == 0.0 || b.x == 0.0) {{
+		return t - float(idx) < 0.5 ? a : b;
+	}}
+	return mix(a, b, t - float(idx));
+}}
+",
+			idx, TEXTURE_MARGIN, interpolation, time_offset, base_idx, ENVELOPE_HOP, PITCH_HOP
+		)
+}
+
+/// Returns the functions reading the buffers of the analyses, which return silence for the
+/// analyses disabled, whose buffers are not bound.
+fn sound_storage_analysisfunction(idx: usize, options: &StorageOptions) -> String {
+	let stft = if options.stft.is_some() {
+		format!(
+			"	uint bins = iSTFTSize{0} / 2u + 1u;
+	if (iSTFTSize{0} == 0u || frame < iSTFTBaseFrame{0} || bin >= bins) {{
+		return vec4(0.0);
+	}}
+	uint idx = frame - iSTFTBaseFrame{0};
+	if (idx >= iSTFTFrames{0}) {{
+		return vec4(0.0);
+	}}
+	return iAudioSTFT{0}[idx * bins + bin];",
+			idx
+		)
+	} else {
+		"	return vec4(0.0);".to_string()
+	};
+	let spectrum = if options.spectrum {
+		format!(
+			"	if (iSpectrumHop{0} == 0u) {{
+		return vec2(0.0);
+	}}
+	uint frame = uint(max(time, 0.0) * float(iChannelSampleRate{0})) / iSpectrumHop{0};
+	if (frame < iSpectrumBaseFrame{0} || frame - iSpectrumBaseFrame{0} >= iSpectrumFrames{0}) {{
+		return vec2(0.0);
+	}}
+	float t = clamp(x, 0.0, 1.0) * float({1} - 1);
+	uint i = min(uint(t), {1}u - 2u);
+	float p = t - float(i);
+	uint row = (frame - iSpectrumBaseFrame{0}) * {1}u;
+	return iAudioSpectrum{0}[row + i] * (1.0 - p) + iAudioSpectrum{0}[row + i + 1u] * p;",
+			idx, SPECTRUM_LEN
+		)
+	} else {
+		"	return vec2(0.0);".to_string()
+	};
+	let (envelope, beat, next_beat) = if options.envelope {
+		(
+			format!(
+				"	int frames = int(iEnvelopeFrames{0});
+	if (frames == 0) {{
+		return vec2(0.0);
+	}}
+	return iAudioEnvelope{0}[uint(clamp(idx - int(iEnvelopeBaseFrame{0}), 0, frames - 1))];",
+				idx
+			),
+			format!(
+				"	float beat = -1.0;
+	for (uint i = 0u; i < iBeatCount{0}; i++) {{
+		float t = iAudioEnvelope{0}[iEnvelopeFrames{0} + i].x;
+		if (t <= time) {{
+			beat = t;
+		}}
+	}}
+	return beat;",
+				idx
+			),
+			format!(
+				"	for (uint i = 0u; i < iBeatCount{0}; i++) {{
+		float t = iAudioEnvelope{0}[iEnvelopeFrames{0} + i].x;
+		if (t > time) {{
+			return t;
+		}}
+	}}
+	return -1.0;",
+				idx
+			),
+		)
+	} else {
+		(
+			"	return vec2(0.0);".to_string(),
+			"	return -1.0;".to_string(),
+			"	return -1.0;".to_string(),
+		)
+	};
+	let pitch = if options.pitch {
+		format!(
+			"	int frames = int(iPitchFrames{0});
+	if (frames == 0) {{
+		return vec2(0.0);
+	}}
+	return iAudioPitch{0}[uint(clamp(idx - int(iPitchBaseFrame{0}), 0, frames - 1))];",
+			idx
+		)
+	} else {
+		"	return vec2(0.0);".to_string()
+	};
+	format!(
+		"vec4 soundSTFTFetch{0}(uint frame, uint bin) {{
+{1}
+}}
+vec2 soundSpectrumRow{0}(float time, float x) {{
+{2}
+}}
+vec2 soundEnvelopeTexel{0}(int idx) {{
+{3}
+}}
+float soundLastBeat{0}(float time) {{
+{4}
+}}
+float soundNextBeat{0}(float time) {{
+{5}
+}}
+vec2 soundPitchTexel{0}(int idx) {{
+{6}
 }}
 ",
-		idx
+		idx, stft, spectrum, envelope, beat, next_beat, pitch
 	)
 }
 
-fn sound_storage_bind_group_layout_entries(len: u32) -> Vec<BindGroupLayoutEntry> {
-	(0..len)
-		.flat_map(|i| {
-			vec![
-				BindGroupLayoutEntry {
-					binding: i * 2,
-					visibility: ShaderStages::COMPUTE,
-					ty: BindingType::Buffer {
-						ty: BufferBindingType::Storage { read_only: false },
-						has_dynamic_offset: false,
-						min_binding_size: None,
-					},
-					count: None,
-				},
-				BindGroupLayoutEntry {
-					binding: i * 2 + 1,
-					visibility: ShaderStages::COMPUTE,
-					ty: BindingType::Buffer {
-						ty: BufferBindingType::Uniform,
-						has_dynamic_offset: false,
-						min_binding_size: None,
-					},
-					count: None,
-				},
-			]
+fn sound_storage_aliases(idx: usize, name: &str) -> String {
+	ALIASED_IDENTIFIERS
+		.iter()
+		.map(|ident| format!("#define {0}_{1} {0}{2}\n", ident, name, idx))
+		.collect()
+}
+
+/// Checks that the names of resources are valid identifiers without duplicates, and that
+/// all aliases referred in `code` are defined.
+pub fn check_resource_names(code: &str, resources: &[StorageBinding]) -> Result<(), String> {
+	let is_ident_char = |c: char| c.is_ascii_alphanumeric() || c == '_';
+	let names: Vec<&str> = resources
+		.iter()
+		.filter_map(|resource| resource.options.name.as_deref())
+		.collect();
+	for (i, name) in names.iter().enumerate() {
+		if name.is_empty() || !name.chars().all(is_ident_char) {
+			return Err(format!("invalid resource name: \"{}\"", name));
+		}
+		if names[..i].contains(name) {
+			return Err(format!("duplicated resource name: {}", name));
+		}
+	}
+	for ident in ALIASED_IDENTIFIERS.iter() {
+		let prefix = format!("{}_", ident);
+		for (pos, _) in code.match_indices(&prefix) {
+			if code[..pos].ends_with(is_ident_char) {
+				continue;
+			}
+			let rest = &code[pos + prefix.len()..];
+			let name = &rest[..rest.find(|c| !is_ident_char(c)).unwrap_or(rest.len())];
+			if !names.contains(&name) {
+				return Err(format!("undefined resource name: {}{}", prefix, name));
+			}
+		}
+	}
+	Ok(())
+}
+
+fn sound_storage_bind_group_layout_entries(
+	storages: &[StorageBinding],
+) -> Vec<BindGroupLayoutEntry> {
+	storages
+		.iter()
+		.enumerate()
+		.flat_map(|(i, storage)| {
+			sound_storage_slots(&storage.options)
+				.into_iter()
+				.map(move |slot| {
+					let ty = if slot == INFO_SLOT {
+						BufferBindingType::Uniform
+					} else {
+						BufferBindingType::Storage { read_only: false }
+					};
+					BindGroupLayoutEntry {
+						binding: (i * STORAGE_SLOTS + slot) as u32,
+						visibility: ShaderStages::COMPUTE,
+						ty: BindingType::Buffer {
+							ty,
+							has_dynamic_offset: false,
+							min_binding_size: None,
+						},
+						count: None,
+					}
+				})
 		})
 		.collect()
 }
 
-fn sound_storage_buffers(
+fn upload_sound_storages(
 	device: &Device,
-	storages: &[Arc<Mutex<WavTextureMaker>>],
+	storages: &mut [StorageBinding],
 	buffer_length: usize,
 	device_sample_rate: u32,
-) -> Vec<Buffer> {
-	storages
-		.iter()
-		.flat_map(|storage| {
-			let mut storage = storage.lock().unwrap();
-			let hound::WavSpec {
-				sample_rate,
-				channels,
-				..
-			} = storage.spec();
+) {
+	storages.iter_mut().for_each(|storage| {
+		if storage.options.random_access && storage.buffers.is_some() {
+			return;
+		}
+		let SourceSpec {
+			sample_rate,
+			channels,
+		} = storage.source.lock().unwrap().spec();
+		let (vec, total_frames, ended, block_start) = if storage.options.random_access {
+			let vec = storage.bank_texels();
+			let total_frames = vec.len() - 2 * TEXTURE_MARGIN;
+			(vec, total_frames, false, 0)
+		} else {
 			let buffer_length =
 				(buffer_length as f64 * sample_rate as f64 / device_sample_rate as f64) as usize;
-			if storage.buffer_len() < buffer_length {
-				println!("not enough textures!");
+			let required = buffer_length * channels as usize;
+			if storage.source.lock().unwrap().buffer_len() < required {
+				eprintln!("not enough textures!");
 			}
-			let vec = storage.next_buffer(buffer_length);
-			let storage_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-				label: None,
-				contents: bytemuck::cast_slice(&vec),
-				usage: BufferUsages::STORAGE,
-			});
-			drop(storage);
-			let uniform_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
-				label: None,
-				contents: bytemuck::cast_slice(&[sample_rate, channels as u32]),
-				usage: BufferUsages::UNIFORM,
-			});
-			vec![storage_buffer, uniform_buffer]
-		})
-		.collect()
+			let total_frames = storage.source.lock().unwrap().total_frames();
+			let ended = total_frames.is_some_and(|total| storage.position >= total);
+			let block_start = storage.position;
+			storage.position += buffer_length;
+			let vec = storage.next_texels(buffer_length);
+			(vec, total_frames.unwrap_or(0), ended, block_start)
+		};
+		let channels_len = channels as usize;
+		let frames = &vec.samples
+			[TEXTURE_MARGIN * channels_len..vec.samples.len() - TEXTURE_MARGIN * channels_len];
+		let (stft_info, stft_bins) = match storage.stft.as_mut() {
+			Some(stft) => {
+				let (base_frame, bins) = stft.process(frames);
+				let info = [
+					stft.size() as u32,
+					stft.hop() as u32,
+					base_frame as u32,
+					(bins.len() / stft.bins()) as u32,
+				];
+				(info, bins)
+			}
+			None => ([0; 4], Vec::new()),
+		};
+		let (spectrum_info, spectrum_rows) = match storage.spectrum.as_mut() {
+			Some(spectrum) => {
+				let (base_frame, rows) = spectrum.process(frames);
+				let info = [
+					spectrum.hop() as u32,
+					base_frame as u32,
+					(rows.len() / SPECTRUM_LEN) as u32,
+				];
+				(info, rows)
+			}
+			None => ([0; 3], Vec::new()),
+		};
+		// the rows of the frames are followed by the beats, from the last one before the block
+		// to the last one analysed ahead.
+		let (envelope_info, envelope_rows) = match storage.envelope.as_mut() {
+			Some(envelope) => {
+				// `vec` begins `TEXTURE_MARGIN` frames before the block, and is followed by
+				// the samples reserved by the source.
+				let vec_end = block_start + vec.len() - TEXTURE_MARGIN;
+				if envelope.analysed() < vec_end {
+					let begin = envelope.analysed() + TEXTURE_MARGIN - block_start;
+					envelope.append(&vec.samples[begin * channels_len..]);
+				}
+				if !storage.options.random_access {
+					let source = storage.source.lock().unwrap();
+					let reserved = source.reserved_samples();
+					let skip = (envelope.analysed() - vec_end) * channels_len;
+					if skip < reserved.len() {
+						envelope.append(&reserved[skip..]);
+					}
+				}
+				let (base_frame, rows, beats) = envelope.rows_from(block_start);
+				let info = [base_frame as u32, rows.len() as u32, beats.len() as u32];
+				(info, [rows, beats].concat())
+			}
+			None => ([0; 3], Vec::new()),
+		};
+		let (pitch_info, pitch_rows) = match storage.pitch.as_mut() {
+			Some(pitch) => {
+				let (base_frame, rows) = pitch.process(frames);
+				([base_frame as u32, rows.len() as u32], rows)
+			}
+			None => ([0; 2], Vec::new()),
+		};
+		let texture = device.create_buffer_init(&util::BufferInitDescriptor {
+			label: None,
+			contents: bytemuck::cast_slice(&vec.texels),
+			usage: BufferUsages::STORAGE,
+		});
+		let channels_buffer = device.create_buffer_init(&util::BufferInitDescriptor {
+			label: None,
+			contents: bytemuck::cast_slice(&vec.samples),
+			usage: BufferUsages::STORAGE,
+		});
+		let info = device.create_buffer_init(&util::BufferInitDescriptor {
+			label: None,
+			contents: bytemuck::cast_slice(&[
+				sample_rate,
+				channels as u32,
+				vec.len() as u32,
+				total_frames as u32,
+				ended as u32,
+				stft_info[0],
+				stft_info[1],
+				stft_info[2],
+				stft_info[3],
+				spectrum_info[0],
+				spectrum_info[1],
+				spectrum_info[2],
+				envelope_info[0],
+				envelope_info[1],
+				envelope_info[2],
+				pitch_info[0],
+				pitch_info[1],
+				0,
+				0,
+				0,
+			]),
+			usage: BufferUsages::UNIFORM,
+		});
+		let stft = analysis_buffer(device, storage.stft.is_some(), &stft_bins);
+		let spectrum = analysis_buffer(device, storage.spectrum.is_some(), &spectrum_rows);
+		let envelope = analysis_buffer(device, storage.envelope.is_some(), &envelope_rows);
+		let pitch = analysis_buffer(device, storage.pitch.is_some(), &pitch_rows);
+		storage.buffers = Some(StorageBuffers {
+			texture,
+			channels: channels_buffer,
+			info,
+			stft,
+			spectrum,
+			envelope,
+			pitch,
+		});
+	})
+}
+
+/// Creates the storage buffer of the rows of an analysis, or `None` if it is disabled and
+/// not bound. Empty rows are uploaded as a row of zeros, since empty buffers cannot be bound.
+fn analysis_buffer<T: bytemuck::Pod + Default>(
+	device: &Device,
+	enabled: bool,
+	rows: &[T],
+) -> Option<Buffer> {
+	if !enabled {
+		return None;
+	}
+	let placeholder = [T::default()];
+	let rows = if rows.is_empty() { &placeholder } else { rows };
+	Some(device.create_buffer_init(&util::BufferInitDescriptor {
+		label: None,
+		contents: bytemuck::cast_slice(rows),
+		usage: BufferUsages::STORAGE,
+	}))
 }
 
-fn buffers_to_entries(buffers: &[Buffer]) -> Vec<BindGroupEntry> {
-	buffers
+fn sound_storage_entries(storages: &[StorageBinding]) -> Vec<BindGroupEntry<'_>> {
+	storages
 		.iter()
 		.enumerate()
-		.flat_map(|(i, buffer)| {
-			vec![BindGroupEntry {
-				binding: i as u32,
-				resource: buffer.as_entire_binding(),
-			}]
+		.flat_map(|(i, storage)| {
+			let buffers = storage.buffers.as_ref().unwrap();
+			buffers
+				.slots()
+				.iter()
+				.enumerate()
+				.filter_map(|(slot, buffer)| {
+					buffer.map(|buffer| BindGroupEntry {
+						binding: (i * STORAGE_SLOTS + slot) as u32,
+						resource: buffer.as_entire_binding(),
+					})
+				})
+				.collect::<Vec<_>>()
 		})
 		.collect()
 }
+
+fn sample_library_fetchfunction(len: usize) -> String {
+	format!(
+		"vec2 sampleTexel(int sampleId, int idx) {{
+	uvec4 entry = iSampleTable[sampleId];
+	if (idx < 0 || idx >= int(entry.y)) {{
+		return vec2(0.0);
+	}}
+	return iSampleData[entry.x + uint(idx)];
+}}
+float sampleLength(int sampleId) {{
+	if (sampleId < 0 || sampleId >= {0}) {{
+		return 0.0;
+	}}
+	uvec4 entry = iSampleTable[sampleId];
+	return float(entry.y) / float(entry.z);
+}}
+vec2 sampleFetch(int sampleId, float time) {{
+	if (sampleId < 0 || sampleId >= {0}) {{
+		return vec2(0.0);
+	}}
+	float t = time * float(iSampleTable[sampleId].z);
+	int idx = int(floor(t));
+	float p = t - float(idx);
+	return sampleTexel(sampleId, idx) * (1.0 - p) + sampleTexel(sampleId, idx + 1) * p;
+}}
+",
+		len
+	)
+}
+
+fn sample_library_bind_group_layout_entries() -> Vec<BindGroupLayoutEntry> {
+	(0..2)
+		.map(|binding| BindGroupLayoutEntry {
+			binding,
+			visibility: ShaderStages::COMPUTE,
+			ty: BindingType::Buffer {
+				ty: BufferBindingType::Storage { read_only: false },
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		})
+		.collect()
+}
+
+fn upload_sample_library(
+	device: &Device,
+	layout: &BindGroupLayout,
+	library: &SampleLibrary,
+) -> SampleLibraryBinding {
+	// empty buffers cannot be bound.
+	let data = if library.data().is_empty() {
+		&[[0.0; 2]][..]
+	} else {
+		library.data()
+	};
+	let table = if library.is_empty() {
+		&[[0; 4]][..]
+	} else {
+		library.table()
+	};
+	let data = device.create_buffer_init(&util::BufferInitDescriptor {
+		label: None,
+		contents: bytemuck::cast_slice(data),
+		usage: BufferUsages::STORAGE,
+	});
+	let table = device.create_buffer_init(&util::BufferInitDescriptor {
+		label: None,
+		contents: bytemuck::cast_slice(table),
+		usage: BufferUsages::STORAGE,
+	});
+	let bind_group = device.create_bind_group(&BindGroupDescriptor {
+		label: None,
+		layout,
+		entries: &[
+			BindGroupEntry {
+				binding: 0,
+				resource: data.as_entire_binding(),
+			},
+			BindGroupEntry {
+				binding: 1,
+				resource: table.as_entire_binding(),
+			},
+		],
+	});
+	SampleLibraryBinding {
+		_data: data,
+		_table: table,
+		bind_group,
+	}
+}
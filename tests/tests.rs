@@ -1,5 +1,10 @@
 use hound::WavReader;
-use sound_shader::{AudioDevice, ShaderStreamDescriptor};
+use sound_shader::{
+    AudioDevice, ConvolutionOptions, Interpolation, LimiterOptions, PostChainOptions,
+    ShaderStreamDescriptor, SoundSource, SourceSpec, SpectralInput, SpectralOptions,
+    StorageOptions, TextureMaker,
+};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
@@ -40,6 +45,7 @@ fn simple_sine() {
 }
 
 #[test]
+#[allow(clippy::approx_constant)]
 fn silent_and_record() {
     let record = Arc::new(Mutex::new(Vec::new()));
     let duration = Duration::from_secs_f32(6.28);
@@ -159,3 +165,524 @@ fn decryption() {
             );
         });
 }
+
+#[test]
+fn effect_block() {
+    let mut effect = sound_shader::ShaderEffect::new(sound_shader::ShaderEffectDescriptor {
+        shader_source: include_str!("half-gain.comp"),
+        ..Default::default()
+    });
+    let sample_rate = 44100;
+    (0..10).for_each(|block| {
+        let input: Vec<f32> = (0..1024)
+            .flat_map(|i| {
+                let t = (block * 1024 + i) as f32 / sample_rate as f32;
+                vec![f32::sin(t * 1000.0), f32::cos(t * 1000.0)]
+            })
+            .collect();
+        let output = effect.process(sample_rate, &input);
+        assert_eq!(input.len(), output.len());
+        input.iter().zip(&output).for_each(|(a, b)| {
+            assert!(
+                f32::abs(a * 0.5 - b) < 0.0001,
+                "input: {}\noutput: {}",
+                a,
+                b
+            );
+        });
+    });
+}
+
+#[test]
+fn spectral_block() {
+    let stft = sound_shader::StftOptions {
+        size: 1024,
+        hop: 256,
+        window: sound_shader::Window::Hann,
+    };
+    let mut spectral = sound_shader::ShaderSpectral::new(sound_shader::ShaderSpectralDescriptor {
+        shader_source: include_str!("spectral-pass.comp"),
+        stft,
+        ..Default::default()
+    });
+    let sample_rate = 44100;
+    let input: Vec<f32> = (0..10 * 1000)
+        .flat_map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            vec![f32::sin(t * 1000.0), f32::cos(t * 1000.0)]
+        })
+        .collect();
+    let output: Vec<f32> = input
+        .chunks(2000)
+        .flat_map(|block| spectral.process(sample_rate, block))
+        .collect();
+    assert_eq!(input.len(), output.len());
+    // the output is delayed by the FFT size.
+    input.iter().zip(&output[1024 * 2..]).for_each(|(a, b)| {
+        assert!(f32::abs(a - b) < 0.001, "input: {}\noutput: {}", a, b);
+    });
+}
+
+#[test]
+fn spectral_stream() {
+    let sample_rate = 44100;
+    let stft = sound_shader::StftOptions {
+        size: 1024,
+        hop: 256,
+        window: sound_shader::Window::Hann,
+    };
+    let input: Vec<f32> = (0..sample_rate)
+        .flat_map(|i| {
+            let t = i as f32 / sample_rate as f32;
+            vec![f32::sin(t * 1000.0), f32::cos(t * 1000.0)]
+        })
+        .collect();
+    let spec = SourceSpec {
+        sample_rate,
+        channels: 2,
+    };
+    let source: Arc<Mutex<dyn SoundSource>> = Arc::new(Mutex::new(
+        TextureMaker::from_vec(spec, input.clone()).unwrap(),
+    ));
+    let descs = vec![
+        // the dry output of `mainSound`
+        ShaderStreamDescriptor {
+            shader_source: include_str!("spectral-sound.comp"),
+            spectral: Some(SpectralOptions {
+                stft: stft.clone(),
+                input: SpectralInput::Sound,
+            }),
+            ..Default::default()
+        },
+        ShaderStreamDescriptor {
+            shader_source: include_str!("spectral-pass.comp"),
+            sound_sources: vec![source],
+            spectral: Some(SpectralOptions {
+                stft,
+                input: SpectralInput::Resource(0),
+            }),
+            ..Default::default()
+        },
+    ];
+    descs.into_iter().for_each(|desc| {
+        let output = sound_shader::write_buffer(desc, sample_rate, Duration::from_secs(1));
+        assert_eq!(input.len(), output.len());
+        // the output is delayed by the FFT size.
+        input.iter().zip(&output[1024 * 2..]).for_each(|(a, b)| {
+            assert!(f32::abs(a - b) < 0.001, "input: {}\noutput: {}", a, b);
+        });
+    });
+}
+
+#[test]
+fn memory_source() {
+    let sample_rate = 44100;
+    let samples: Vec<f32> = (0..sample_rate * 4)
+        .map(|i| (i % 200) as f32 / 100.0 - 1.0)
+        .collect();
+    let spec = SourceSpec {
+        sample_rate,
+        channels: 2,
+    };
+    let source: Arc<Mutex<dyn SoundSource>> = Arc::new(Mutex::new(
+        TextureMaker::from_vec(spec, samples.clone()).unwrap(),
+    ));
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("texel.comp"),
+        sound_sources: vec![source],
+        ..Default::default()
+    };
+    let buffer = sound_shader::write_buffer(desc, sample_rate, Duration::from_secs(1));
+    buffer
+        .iter()
+        .zip(&samples)
+        .enumerate()
+        .for_each(|(i, (a, b))| {
+            assert!(
+                f32::abs(a - b) < 0.0001,
+                "frame: {}\nchannel: {}\nanswer: {}\nrendered: {}",
+                i / 2,
+                i % 2,
+                b,
+                a
+            );
+        });
+}
+
+#[test]
+fn interpolation() {
+    // a resource of 11025 Hz is upsampled to 44100 Hz by `soundTexture0`.
+    let source_rate = 11025;
+    let sine = |t: f32| {
+        let t = 2.0 * std::f32::consts::PI * 500.0 * t;
+        [f32::cos(t), f32::sin(t)]
+    };
+    let samples: Vec<f32> = (0..source_rate * 2)
+        .flat_map(|i| sine(i as f32 / source_rate as f32))
+        .collect();
+    let texel = |i: i64| [samples[i as usize * 2], samples[i as usize * 2 + 1]];
+    // CPU references of each interpolation between the texels `idx` and `idx + 1`
+    let linear = |idx: i64, p: f32| {
+        let (y0, y1) = (texel(idx), texel(idx + 1));
+        [0, 1].map(|c| y0[c] * (1.0 - p) + y1[c] * p)
+    };
+    let cubic = |idx: i64, p: f32| {
+        let y = [texel(idx - 1), texel(idx), texel(idx + 1), texel(idx + 2)];
+        [0, 1].map(|c| {
+            let c1 = 0.5 * (y[2][c] - y[0][c]);
+            let c2 = y[0][c] - 2.5 * y[1][c] + 2.0 * y[2][c] - 0.5 * y[3][c];
+            let c3 = 0.5 * (y[3][c] - y[0][c]) + 1.5 * (y[1][c] - y[2][c]);
+            ((c3 * p + c2) * p + c1) * p + y[1][c]
+        })
+    };
+    let sinc = |idx: i64, p: f32| {
+        (-7..=8).fold([0.0, 0.0], |sum, k| {
+            let x = k as f32 - p;
+            let window = 0.5 + 0.5 * f32::cos(std::f32::consts::PI * x / 8.0);
+            let sinc = if f32::abs(x) > 0.000001 {
+                f32::sin(std::f32::consts::PI * x) / (std::f32::consts::PI * x)
+            } else {
+                1.0
+            };
+            let y = texel(idx + k);
+            [sum[0] + y[0] * sinc * window, sum[1] + y[1] * sinc * window]
+        })
+    };
+    for interpolation in [
+        Interpolation::Linear,
+        Interpolation::Cubic,
+        Interpolation::Sinc,
+    ] {
+        let spec = SourceSpec {
+            sample_rate: source_rate,
+            channels: 2,
+        };
+        let source: Arc<Mutex<dyn SoundSource>> = Arc::new(Mutex::new(
+            TextureMaker::from_vec(spec, samples.clone()).unwrap(),
+        ));
+        let storage_options = [StorageOptions {
+            interpolation,
+            ..Default::default()
+        }];
+        let desc = ShaderStreamDescriptor {
+            shader_source: include_str!("texture.comp"),
+            sound_sources: vec![source],
+            storage_options: &storage_options,
+            ..Default::default()
+        };
+        let buffer = sound_shader::write_buffer(desc, 44100, Duration::from_secs(1));
+        assert_eq!(buffer.len(), 44100 * 2);
+        // the first texels are skipped, since the kernels reach before the beginning.
+        buffer.chunks(2).enumerate().skip(64).for_each(|(i, x)| {
+            let (idx, p) = ((i / 4) as i64, (i % 4) as f32 / 4.0);
+            let answer = match interpolation {
+                Interpolation::Linear => linear(idx, p),
+                Interpolation::Cubic => cubic(idx, p),
+                Interpolation::Sinc => sinc(idx, p),
+            };
+            let exact = sine(i as f32 / 44100.0);
+            assert!(
+                f32::abs(x[0] - answer[0]) < 0.001 && f32::abs(x[1] - answer[1]) < 0.001,
+                "{:?}\nframe: {}\nanswer: {:?}\nrendered: {:?}",
+                interpolation,
+                i,
+                answer,
+                x
+            );
+            // every interpolation is close to the original signal.
+            assert!(
+                f32::abs(x[0] - exact[0]) < 0.05 && f32::abs(x[1] - exact[1]) < 0.05,
+                "{:?}\nframe: {}\nexact: {:?}\nrendered: {:?}",
+                interpolation,
+                i,
+                exact,
+                x
+            );
+        });
+    }
+}
+
+#[derive(Clone, Default)]
+struct BufferSink(Arc<Mutex<Vec<f32>>>);
+
+impl sound_shader::AudioSink for BufferSink {
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.0.lock().unwrap().extend_from_slice(samples);
+        Ok(())
+    }
+}
+
+/// `BufferSink` which requires a sample rate, like an audio device
+struct FixedRateSink(BufferSink, u32);
+
+impl sound_shader::AudioSink for FixedRateSink {
+    fn sample_rate(&self) -> Option<u32> {
+        Some(self.1)
+    }
+    fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        sound_shader::AudioSink::write(&mut self.0, samples)
+    }
+}
+
+#[test]
+fn offline_sample_rate() {
+    let sink = BufferSink::default();
+    let mut sinks: Vec<Box<dyn sound_shader::AudioSink>> = vec![Box::new(sink.clone())];
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("simple-sine.comp"),
+        sample_rate: Some(96000),
+        ..Default::default()
+    };
+    let running = std::sync::atomic::AtomicBool::new(true);
+    sound_shader::run(
+        desc,
+        &mut sinks,
+        Some(Duration::from_secs_f32(2.5)),
+        &running,
+    )
+    .unwrap();
+
+    let record = sink.0.lock().unwrap();
+    assert_eq!(record.len(), 96000 * 5);
+    record.chunks(2).enumerate().for_each(|(i, x)| {
+        let t = 2.0 * std::f32::consts::PI * 440.0 * i as f32 / 96000.0;
+        assert!(
+            f32::abs(f32::cos(t) - x[0]) < 0.01 && f32::abs(f32::sin(t) - x[1]) < 0.01,
+            "frame: {}\nanswer: {:?}\nrendered: {:?}",
+            i,
+            (f32::cos(t), f32::sin(t)),
+            x
+        );
+    });
+}
+
+#[test]
+fn sample_library() {
+    let dir = concat!(env!("CARGO_TARGET_TMPDIR"), "/sample-library");
+    std::fs::create_dir_all(dir).unwrap();
+    let write_wav = |name: &str, channels: u16, samples: &[f32]| {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: 44100,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(Path::new(dir).join(name), spec).unwrap();
+        samples
+            .iter()
+            .for_each(|sample| writer.write_sample(*sample).unwrap());
+        writer.finalize().unwrap();
+    };
+    write_wav("a.wav", 1, &[0.0; 22050]);
+    let samples: Vec<f32> = (0..4410 * 2).map(|i| (i % 100) as f32 / 100.0).collect();
+    write_wav("b.wav", 2, &samples);
+
+    let sample_library = [dir];
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("sample-library.comp"),
+        sample_library: &sample_library,
+        ..Default::default()
+    };
+    let buffer = sound_shader::write_buffer(desc, 44100, Duration::from_secs(1));
+    buffer.iter().enumerate().for_each(|(i, a)| {
+        let b = samples.get(i).copied().unwrap_or(0.0) + 0.5;
+        assert!(
+            f32::abs(a - b) < 0.0001,
+            "frame: {}\nchannel: {}\nanswer: {}\nrendered: {}",
+            i / 2,
+            i % 2,
+            b,
+            a
+        );
+    });
+}
+
+#[test]
+fn inverse_fft() {
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("inverse-fft.comp"),
+        inverse_fft: Some(256),
+        ..Default::default()
+    };
+    let buffer = sound_shader::write_buffer(desc, 44100, Duration::from_secs(1));
+    assert_eq!(buffer.len(), 44100 * 2);
+    buffer.chunks(2).enumerate().for_each(|(i, x)| {
+        let t = 2.0 * std::f32::consts::PI * 8.0 * (i % 256) as f32 / 256.0;
+        assert!(
+            f32::abs(x[0] - f32::cos(t)) < 0.001 && f32::abs(x[1] - f32::sin(t)) < 0.001,
+            "frame: {}\noutput: {:?}",
+            i,
+            x
+        );
+    });
+}
+
+#[test]
+fn convolution() {
+    let dir = concat!(env!("CARGO_TARGET_TMPDIR"), "/convolution");
+    std::fs::create_dir_all(dir).unwrap();
+    let path = Path::new(dir).join("echo.wav");
+    let spec = hound::WavSpec {
+        channels: 1,
+        sample_rate: 44100,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+    (0..2206).for_each(|i| {
+        let sample = match i {
+            0 => 1.0,
+            2205 => 0.5,
+            _ => 0.0,
+        };
+        writer.write_sample(sample).unwrap();
+    });
+    writer.finalize().unwrap();
+
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("simple-sine.comp"),
+        convolution: Some(ConvolutionOptions {
+            impulse_response: path,
+            wet: 1.0,
+            dry: 0.0,
+        }),
+        ..Default::default()
+    };
+    let buffer = sound_shader::write_buffer(desc, 44100, Duration::from_secs(1));
+    assert_eq!(buffer.len(), 44100 * 2);
+    let sine = |i: usize| {
+        let t = 2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0;
+        (f32::cos(t), f32::sin(t))
+    };
+    buffer.chunks(2).enumerate().for_each(|(i, x)| {
+        let (mut a, mut b) = sine(i);
+        if i >= 2205 {
+            let echo = sine(i - 2205);
+            a += echo.0 * 0.5;
+            b += echo.1 * 0.5;
+        }
+        assert!(
+            f32::abs(x[0] - a) < 0.001 && f32::abs(x[1] - b) < 0.001,
+            "frame: {}\noutput: {:?}\nanswer: {:?}",
+            i,
+            x,
+            (a, b)
+        );
+    });
+}
+
+#[test]
+fn post_chain_limiter() {
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("overshoot.comp"),
+        post_chain: Some(PostChainOptions {
+            limiter: Some(LimiterOptions {
+                ceiling: -1.0,
+                ..Default::default()
+            }),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let buffer = sound_shader::write_buffer(desc, 44100, Duration::from_secs(1));
+    assert_eq!(buffer.len(), 44100 * 2);
+    let ceiling = f32::powf(10.0, -1.0 / 20.0);
+    let peak = buffer.iter().fold(0.0, |peak: f32, x| peak.max(x.abs()));
+    assert!(peak <= ceiling && peak > ceiling * 0.99, "peak: {}", peak);
+}
+
+#[test]
+fn oversampling() {
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("simple-sine.comp"),
+        oversampling: Some(4),
+        ..Default::default()
+    };
+    let buffer = sound_shader::write_buffer(desc, 44100, Duration::from_secs(1));
+    assert_eq!(buffer.len(), 44100 * 2);
+    // the delay is compensated. The first 32 frames are filtered with silence before the start.
+    buffer.chunks(2).enumerate().skip(32).for_each(|(i, x)| {
+        let t = 2.0 * std::f32::consts::PI * 440.0 * i as f32 / 44100.0;
+        assert!(
+            f32::abs(x[0] - f32::cos(t)) < 0.01 && f32::abs(x[1] - f32::sin(t)) < 0.01,
+            "frame: {}\noutput: {:?}",
+            i,
+            x
+        );
+    });
+}
+
+#[test]
+fn oversampling_texel() {
+    let sample_rate = 44100;
+    let samples: Vec<f32> = (0..sample_rate * 4)
+        .flat_map(|i| {
+            let t = 2.0 * std::f32::consts::PI * 50.0 * i as f32 / sample_rate as f32;
+            vec![f32::cos(t), f32::sin(t)]
+        })
+        .collect();
+    let spec = SourceSpec {
+        sample_rate,
+        channels: 2,
+    };
+    let render = |oversampling| {
+        let source: Arc<Mutex<dyn SoundSource>> = Arc::new(Mutex::new(
+            TextureMaker::from_vec(spec, samples.clone()).unwrap(),
+        ));
+        let desc = ShaderStreamDescriptor {
+            shader_source: include_str!("texel.comp"),
+            sound_sources: vec![source],
+            oversampling,
+            ..Default::default()
+        };
+        sound_shader::write_buffer(desc, sample_rate, Duration::from_secs(3))
+    };
+    let buffer = render(None);
+    let oversampled = render(Some(4));
+    assert_eq!(oversampled.len(), buffer.len());
+    // the resource is read at the same frames in every block.
+    oversampled
+        .iter()
+        .zip(&buffer)
+        .enumerate()
+        .skip(32 * 2)
+        .for_each(|(i, (a, b))| {
+            assert!(
+                f32::abs(a - b) < 0.01,
+                "frame: {}
+oversampled: {}
+rendered: {}",
+                i / 2,
+                a,
+                b
+            );
+        });
+}
+
+#[test]
+fn resampled_sample_rate() {
+    let sink = BufferSink::default();
+    let mut sinks: Vec<Box<dyn sound_shader::AudioSink>> =
+        vec![Box::new(FixedRateSink(sink.clone(), 48000))];
+    let desc = ShaderStreamDescriptor {
+        shader_source: include_str!("sample-rate.comp"),
+        sample_rate: Some(44100),
+        ..Default::default()
+    };
+    let running = std::sync::atomic::AtomicBool::new(true);
+    sound_shader::run(desc, &mut sinks, Some(Duration::from_secs(2)), &running).unwrap();
+
+    let record = sink.0.lock().unwrap();
+    assert_eq!(record.len(), 48000 * 4);
+    // rendered at 44100 Hz without delay. The first frames are filtered with silence before
+    // the start.
+    record.chunks(2).enumerate().skip(64).for_each(|(i, x)| {
+        let t = 2.0 * std::f64::consts::PI * 440.0 * i as f64 / 48000.0;
+        assert!(
+            f32::abs(f64::cos(t) as f32 - x[0]) < 0.01 && f32::abs(0.441 - x[1]) < 0.01,
+            "frame: {}\nrendered: {:?}",
+            i,
+            x
+        );
+    });
+}